@@ -0,0 +1,79 @@
+use core::cell::Cell;
+use cortex_m::peripheral::{DCB, DWT};
+use stm32f3xx_hal::time::fixed_point::FixedPoint;
+use stm32f3xx_hal::time::rate::Hertz;
+
+/// Current value of the DWT cycle counter (0 until [CycleClock::new] started it)
+///
+/// Cheap enough to be read in an ISR to time stamp an event.
+pub fn cycles() -> u32 {
+    DWT::cycle_count()
+}
+
+/// Microsecond time base derived from the DWT cycle counter
+///
+/// The 32 bit counter wraps after 2^32 cycles (~89s at 48MHz), the clock extends it to 64 bit with every conversion.
+/// Converted cycle counts must therefore be less than 2^31 cycles (~44s at 48MHz) apart from the newest converted one
+/// and a conversion must happen at least that often.
+pub struct CycleClock {
+    cycles_per_us: u32,
+    /// Newest converted cycle count and its extended value
+    last: Cell<u32>,
+    extended: Cell<u64>,
+}
+
+impl CycleClock {
+    /// Starts the cycle counter running at `sysclk`
+    pub fn new(dcb: &mut DCB, dwt: &mut DWT, sysclk: Hertz) -> Self {
+        dcb.enable_trace();
+        dwt.enable_cycle_counter();
+        Self {
+            cycles_per_us: sysclk.integer() / 1_000_000,
+            last: Cell::new(cycles()),
+            extended: Cell::new(0),
+        }
+    }
+    /// Time since [CycleClock::new] in µs
+    pub fn now_us(&self) -> u64 {
+        self.to_us(cycles())
+    }
+    /// Converts a cycle count taken by [cycles] to µs since [CycleClock::new]
+    pub fn to_us(&self, cycles: u32) -> u64 {
+        let delta = i64::from(cycles.wrapping_sub(self.last.get()) as i32);
+        let extended = self.extended.get().saturating_add_signed(delta);
+        if delta > 0 {
+            self.last.set(cycles);
+            self.extended.set(extended);
+        }
+        extended / u64::from(self.cycles_per_us)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clock(last: u32) -> CycleClock {
+        CycleClock {
+            cycles_per_us: 48,
+            last: Cell::new(last),
+            extended: Cell::new(0),
+        }
+    }
+
+    #[test]
+    fn extends_over_the_wrap() {
+        let clock = clock(u32::MAX - 47);
+        assert_eq!(clock.to_us(0), 1);
+        assert_eq!(clock.to_us(48 * 10), 11);
+    }
+
+    #[test]
+    fn older_count_does_not_advance() {
+        let clock = clock(0);
+        assert_eq!(clock.to_us(48 * 100), 100);
+        // edge captured before the newest conversion
+        assert_eq!(clock.to_us(48 * 90), 90);
+        assert_eq!(clock.to_us(48 * 101), 101);
+    }
+}
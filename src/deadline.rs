@@ -0,0 +1,11 @@
+use core::future::Future;
+use lilos::exec::with_deadline;
+use lilos::time::TickTime;
+
+/// Runs `future` until it finishes or `deadline` (if any) passes
+pub(crate) async fn before<F: Future>(deadline: Option<TickTime>, future: F) -> Option<F::Output> {
+    match deadline {
+        Some(deadline) => with_deadline(deadline, future).await,
+        None => Some(future.await),
+    }
+}
@@ -0,0 +1,28 @@
+use stm32f3xx_hal::dma::{Channel, Direction, Event, Increment};
+
+/// DMA_CCR: TCIE and TEIE
+pub(crate) const CCR_IRQ_MASK: u32 = 0b1010;
+/// Bytes one transfer of a channel can move (16 bit DMA_CNDTR)
+pub const MAX_TRANSFER: usize = u16::MAX as usize;
+
+/// Sets up `ch` for a transfer of `len` bytes between the register at `dr` and memory
+///
+/// `len` must not exceed [MAX_TRANSFER], the callers split or reject longer buffers.
+pub(crate) fn configure<C: Channel>(
+    ch: &mut C,
+    dr: u32,
+    mem: u32,
+    len: usize,
+    direction: Direction,
+) {
+    ch.disable();
+    ch.clear_event(Event::Any);
+    unsafe {
+        ch.set_peripheral_address(dr, Increment::Disable);
+        ch.set_memory_address(mem, Increment::Enable);
+    }
+    debug_assert!(len <= MAX_TRANSFER);
+    ch.set_transfer_length(len as u16);
+    ch.set_word_size::<u8>();
+    ch.set_direction(direction);
+}
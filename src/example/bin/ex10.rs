@@ -0,0 +1,100 @@
+#![no_main]
+#![no_std]
+use core::convert::Infallible;
+use core::panic::PanicInfo;
+use core::pin::pin;
+use core::time::Duration;
+use defmt::println;
+use defmt_rtt as _;
+use stm32f3xx_hal::pac::{CorePeripherals, Peripherals};
+use wonderos::cycle_clock::CycleClock;
+use wonderos::l3gd20::async_spi::AsyncSpiDevice;
+use wonderos::l3gd20::orientation::Orientation;
+use wonderos::l3gd20::{DataReadyMode, L3gd20};
+use wonderos::lsm303dlhc::{AsyncI2cDevice, Lsm303dlhc, ACCEL_ADDR, MAGNETO_ADDR};
+use wonderos::mutex::Mutex;
+use wonderos::sensor_registers::SensorRegisters;
+use wonderos::stm32f3_disco_def::{Board, GyroInt2, HostI2c};
+
+#[panic_handler]
+fn panic_handler(_info: &PanicInfo) -> ! {
+    println!("Paniced");
+    loop {}
+}
+
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    // Take Cortex-M and STM32 Peripherials
+    let mut core = CorePeripherals::take().unwrap();
+    let p = Peripherals::take().unwrap();
+
+    // Create Board Abstraction
+    let b = Board::new(p);
+    // µs time stamps of the gyro samples
+    let clock = CycleClock::new(&mut core.DCB, &mut core.DWT, b.clocks.sysclk());
+
+    // Sensors and orientation are shared by the integration and the host task
+    let gyro = Mutex::new(L3gd20::new(b.gyro_spi, b.gyro_cs));
+    let compass = Mutex::new(Lsm303dlhc::new(
+        b.i2c.device(ACCEL_ADDR),
+        b.i2c.device(MAGNETO_ADDR),
+    ));
+    let orientation = Mutex::new(Orientation::new());
+
+    // Integrate the gyro rates on every data ready of INT2
+    let i = pin!(integrate(&gyro, &b.gyro_int2, &clock, &orientation));
+    // Serve the registers to the host on I2C2 (PA9 SCL, PA10 SDA)
+    let registers = SensorRegisters::new(&gyro, &compass, &orientation);
+    let h = pin!(serve_host(b.host_i2c, registers, &compass));
+    // Basic Blinky Task
+    let t = pin!(wonderos::task_blinky(b.east_led));
+    // Always Wake all Tasks Task - the I2C drivers are polled
+    let w = pin!(wonderos::wake());
+    // Give lilos a systick to provide delays
+    lilos::time::initialize_sys_tick(&mut core.SYST, b.clocks.sysclk().0);
+    // Run tasks forever
+    lilos::exec::run_tasks(&mut [i, h, t, w], lilos::exec::ALL_TASKS);
+}
+
+async fn integrate<D: AsyncSpiDevice>(
+    gyro: &Mutex<L3gd20<D>>,
+    int2: &GyroInt2,
+    clock: &CycleClock,
+    orientation: &Mutex<Orientation>,
+) -> Infallible {
+    {
+        let mut gyro = gyro.lock().await;
+        // a disconnected gyro fails instead of blocking the task forever
+        gyro.set_timeout(Some(Duration::from_millis(500)));
+        gyro.enable().await.unwrap();
+        // keep the board still while the bias is determined
+        let _ = gyro.calibrate(64, 300).await;
+        gyro.enable_data_ready_interrupt(DataReadyMode::DataReady)
+            .await
+            .unwrap();
+    }
+    loop {
+        // neither the gyro nor the orientation are locked while waiting for the sample, so the host task can
+        // serve the registers meanwhile
+        int2.wait_for_active().await;
+        let sample = gyro.lock().await.read_sample_at_edge(int2, clock).await;
+        match sample {
+            Ok(sample) => orientation.lock().await.update(&sample),
+            Err(e) => println!("Gyro Error {}", e as usize),
+        }
+    }
+}
+
+async fn serve_host<G: AsyncSpiDevice, A: AsyncI2cDevice>(
+    mut host: HostI2c,
+    mut registers: SensorRegisters<'_, G, A>,
+    compass: &Mutex<Lsm303dlhc<A>>,
+) -> Infallible {
+    compass.lock().await.setup().await.unwrap();
+    loop {
+        // a failed transfer is dropped, the host retries it
+        if let Err(e) = host.serve(&mut registers, None).await {
+            println!("Host I2C Error {}", e as usize);
+        }
+    }
+}
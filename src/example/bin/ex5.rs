@@ -8,9 +8,9 @@ use defmt_rtt as _;
 use lilos::handoff::{Handoff, Pop, Push};
 use stm32f3xx_hal::pac::{CorePeripherals, Peripherals};
 use stm32f3xx_hal::time::fixed_point::FixedPoint;
-use wonderos::l3gd20::L3gd20;
+use wonderos::l3gd20::{DataReadyMode, L3gd20};
 use wonderos::led::Led;
-use wonderos::stm32f3_disco_def::{Board, GyroCs, GyroSpi, NorthEastLed, NorthLed};
+use wonderos::stm32f3_disco_def::{Board, GyroCs, GyroInt2, GyroSpi, NorthEastLed, NorthLed};
 
 #[panic_handler]
 fn panic_handler(_info: &PanicInfo) -> ! {
@@ -26,22 +26,37 @@ fn main() -> ! {
     let mut transfer_x_acc: Handoff<i16> = Handoff::new();
     let (x_acc_tx, x_acc_rx) = transfer_x_acc.split();
 
-    let g = pin!(read_gyro(b.gyro_spi, b.gyro_cs, x_acc_tx));
+    let g = pin!(read_gyro(b.gyro_spi, b.gyro_cs, b.gyro_int2, x_acc_tx));
     let r = pin!(blink_on_acceleration(&mut b.north_led, x_acc_rx));
-    let wake = pin!(wake());
     let blink = pin!(blink_parallel(&mut b.northeast_led));
 
+    // All tasks are woken by interrupts - no wake task needed
     lilos::time::initialize_sys_tick(&mut core.SYST, b.clocks.sysclk().integer());
-    lilos::exec::run_tasks(&mut [g, r, blink, wake], lilos::exec::ALL_TASKS);
+    lilos::exec::run_tasks(&mut [g, r, blink], lilos::exec::ALL_TASKS);
 }
-async fn read_gyro(spi: GyroSpi, cs: GyroCs, mut tx_x_acc: Push<'_, i16>) -> Infallible {
+async fn read_gyro(
+    spi: GyroSpi,
+    cs: GyroCs,
+    int2: GyroInt2,
+    mut tx_x_acc: Push<'_, i16>,
+) -> Infallible {
     // Enable Gyro once
     let mut gyro = L3gd20::new(spi, cs);
-    gyro.enable().await;
-    // Loop results every second
+    // Antwortet der Gyro nicht schlägt der Zugriff nach 500ms fehl statt ewig zu warten
+    gyro.set_timeout(Some(Duration::from_millis(500)));
+    gyro.enable().await.unwrap();
+    // Nullpunkt bestimmen - wurde das Board bewegt bleibt der Bias bei 0
+    let _ = gyro.calibrate(64, 300).await;
+    gyro.enable_data_ready_interrupt(DataReadyMode::DataReady)
+        .await
+        .unwrap();
     loop {
-        // Hole neue Messwerte vom Gyro
-        let (x, _y, _z, _temp) = gyro.read_values().await;
+        // Schlafe bis der Gyro einen neuen Messwert meldet
+        gyro.wait_for_data(&int2).await;
+        // Hole neue Messwerte vom Gyro - fehlgeschlagene Zugriffe werden übersprungen
+        let Ok((x, _y, _z, _temp)) = gyro.read_values().await else {
+            continue;
+        };
         // Push neuen Wert zu receive Task
         // push wartet bis pop durchgeführt wurde
         tx_x_acc.push(x).await
@@ -68,10 +83,3 @@ async fn blink_parallel(led: &mut NorthEastLed) -> Infallible {
         lilos::exec::sleep_for(Duration::from_millis(1000)).await
     }
 }
-
-async fn wake() -> Infallible {
-    loop {
-        lilos::exec::wake_tasks_by_mask(usize::MAX);
-        lilos::exec::yield_cpu().await
-    }
-}
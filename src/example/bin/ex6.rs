@@ -6,6 +6,7 @@ use defmt::println;
 use defmt_rtt as _;
 use stm32f3xx_hal::pac::CorePeripherals;
 use stm32f3xx_hal::pac::Peripherals;
+use wonderos::lsm303dlhc::{Lsm303dlhc, ACCEL_ADDR, MAGNETO_ADDR};
 use wonderos::stm32f3_disco_def::Board;
 #[panic_handler]
 fn panic_handler(_info: &PanicInfo) -> ! {
@@ -25,8 +26,9 @@ fn main() -> ! {
     let t = pin!(wonderos::task_blinky(b.east_led,));
     // Always Wake all Tasks Task
     let w = pin!(wonderos::wake());
-    // Magnetometer logic task
-    let g = pin!(wonderos::task_magnetometer(b.user_button, b.magnetometer));
+    // Magnetometer logic task - accelerometer and magnetometer share I2C1
+    let lsm303 = Lsm303dlhc::new(b.i2c.device(ACCEL_ADDR), b.i2c.device(MAGNETO_ADDR));
+    let g = pin!(wonderos::task_magnetometer(b.user_button, lsm303));
     // Give lilos a systick to provide delays
     lilos::time::initialize_sys_tick(&mut core.SYST, b.clocks.sysclk().0);
     // Run tasks forever
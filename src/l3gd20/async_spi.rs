@@ -1,61 +1,298 @@
+use crate::deadline::before;
 use core::future::Future;
-use core::task::Poll;
+use core::task::{Poll, Waker};
+use embedded_hal::digital::v2::OutputPin;
 use embedded_hal::spi::FullDuplex;
-pub async fn async_read<T: FullDuplex<u8>>(spi: &mut T) -> Result<u8, ()> {
-    AsynSpiRead { spi }.await
+use lilos::exec::Notify;
+use lilos::time::TickTime;
+use stm32f3xx_hal::pac::{interrupt, RCC, SPI1};
+use stm32f3xx_hal::spi::Spi;
+
+/// Errors of the async SPI operations
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SpiError {
+    /// The peripheral reported an error (overrun, mode fault, DMA transfer error)
+    Bus,
+    /// The deadline passed before the operation finished. The peripheral was reset and is ready for the next transfer.
+    Timeout,
+}
+
+/// SPI Events which wake a pending read or write
+#[derive(Clone, Copy, PartialEq)]
+pub enum SpiEvent {
+    RxNotEmpty,
+    TxEmpty,
+}
+
+/// SPI Peripherals which wake the waiting task from their interrupt handler
+pub trait SpiInterrupt {
+    /// Registers the waker and enables the interrupt of `event`
+    ///
+    /// The ISR disables the interrupt again before the task is woken so it only fires once per call.
+    fn listen(&mut self, event: SpiEvent, waker: &Waker);
+    /// Aborts an unfinished transfer and empties the FIFOs while keeping the configuration
+    fn reset(&mut self);
+}
+
+static SPI1_NOTIFY: Notify = Notify::new();
+
+/// SPI_CR2: RXDMAEN, TXDMAEN, ERRIE, RXNEIE and TXEIE are not restored after a reset
+const CR2_RESET_MASK: u32 = 0b1110_0011;
+const APB2RSTR_SPI1RST: u32 = 1 << 12;
+
+/// The SPI1 interrupt must be unmasked in the NVIC
+impl<Pins> SpiInterrupt for Spi<SPI1, Pins, u8> {
+    fn listen(&mut self, event: SpiEvent, waker: &Waker) {
+        SPI1_NOTIFY.subscribe(waker);
+        let spi = unsafe { &*SPI1::ptr() };
+        match event {
+            SpiEvent::RxNotEmpty => spi.cr2.modify(|_, w| w.rxneie().set_bit()),
+            SpiEvent::TxEmpty => spi.cr2.modify(|_, w| w.txeie().set_bit()),
+        }
+    }
+    /// Pulses the reset of SPI1 in RCC - there is no other way to abort a byte in flight
+    fn reset(&mut self) {
+        let spi = unsafe { &*SPI1::ptr() };
+        let rcc = unsafe { &*RCC::ptr() };
+        let cr1 = spi.cr1.read().bits();
+        let cr2 = spi.cr2.read().bits() & !CR2_RESET_MASK;
+        rcc.apb2rstr
+            .modify(|r, w| unsafe { w.bits(r.bits() | APB2RSTR_SPI1RST) });
+        rcc.apb2rstr
+            .modify(|r, w| unsafe { w.bits(r.bits() & !APB2RSTR_SPI1RST) });
+        // CR2 first: data size and FIFO threshold must be set before SPE
+        spi.cr2.write(|w| unsafe { w.bits(cr2) });
+        spi.cr1.write(|w| unsafe { w.bits(cr1) });
+    }
+}
+
+#[interrupt]
+fn SPI1() {
+    let spi = unsafe { &*SPI1::ptr() };
+    // RXNE and TXE are level based - disable them until the next listen
+    spi.cr2
+        .modify(|_, w| w.rxneie().clear_bit().txeie().clear_bit());
+    SPI1_NOTIFY.notify();
+}
+
+/// Async full duplex SPI bus as used by [super::L3gd20]
+///
+/// Implemented for every [FullDuplex] bus with [SpiInterrupt] (byte by byte) and for [super::dma_spi::DmaSpi].
+pub trait AsyncSpiBus {
+    /// Sends the content of `transfer_buffer` and replaces it with the received bytes
+    ///
+    /// Fails with [SpiError::Timeout] if the transfer did not finish before `deadline`.
+    fn async_transfer<'a>(
+        &'a mut self,
+        transfer_buffer: &'a mut [u8],
+        deadline: Option<TickTime>,
+    ) -> impl Future<Output = Result<&'a [u8], SpiError>> + 'a;
+    /// Brings the bus back into an idle state after a transfer future was dropped before it finished
+    fn abort(&mut self);
+}
+
+impl<T: FullDuplex<u8> + SpiInterrupt> AsyncSpiBus for T {
+    fn async_transfer<'a>(
+        &'a mut self,
+        transfer_buffer: &'a mut [u8],
+        deadline: Option<TickTime>,
+    ) -> impl Future<Output = Result<&'a [u8], SpiError>> + 'a {
+        async_transfer(self, transfer_buffer, deadline)
+    }
+    fn abort(&mut self) {
+        self.reset();
+    }
+}
+
+/// A single device on an [AsyncSpiBus] which is selected by its own chip-select
+pub trait AsyncSpiDevice {
+    /// Asserts CS, transfers `transfer_buffer` like [AsyncSpiBus::async_transfer] and releases CS again
+    fn transaction<'a>(
+        &'a mut self,
+        transfer_buffer: &'a mut [u8],
+        deadline: Option<TickTime>,
+    ) -> impl Future<Output = Result<&'a [u8], SpiError>> + 'a;
 }
-pub async fn async_write<T: FullDuplex<u8>>(spi: &mut T, payload: u8) -> Result<(), ()> {
-    AsynSpiWrite { spi, payload }.await
+
+/// Device owning the whole bus
+///
+/// See [super::shared_spi::SharedSpiBus] to use several devices on one bus.
+pub struct ExclusiveSpiDevice<T, E> {
+    spi: T,
+    cs: E,
 }
-pub async fn async_transfer<'a, T: FullDuplex<u8>>(
+
+impl<T: AsyncSpiBus, E: OutputPin> ExclusiveSpiDevice<T, E> {
+    pub fn new(spi: T, mut cs: E) -> Self {
+        cs.set_high().unwrap_or_default();
+        Self { spi, cs }
+    }
+    pub fn release(self) -> (T, E) {
+        (self.spi, self.cs)
+    }
+    pub(super) fn parts(&mut self) -> (&mut T, &mut E) {
+        (&mut self.spi, &mut self.cs)
+    }
+}
+
+impl<T: AsyncSpiBus, E: OutputPin> AsyncSpiDevice for ExclusiveSpiDevice<T, E> {
+    fn transaction<'a>(
+        &'a mut self,
+        transfer_buffer: &'a mut [u8],
+        deadline: Option<TickTime>,
+    ) -> impl Future<Output = Result<&'a [u8], SpiError>> + 'a {
+        async move {
+            let (spi, cs) = self.parts();
+            let mut guard = ChipSelectGuard::new(spi, cs);
+            let res = guard.bus().async_transfer(transfer_buffer, deadline).await;
+            guard.finish();
+            res
+        }
+    }
+}
+
+/// Asserts CS for the lifetime of a transaction
+///
+/// If the guard is dropped without [ChipSelectGuard::finish] (e.g. the transaction future was cancelled by
+/// [lilos::exec::with_timeout]) the bus is aborted before CS is released, so no stale bytes are left for the next
+/// transaction.
+pub struct ChipSelectGuard<'a, T: AsyncSpiBus, E: OutputPin> {
+    spi: &'a mut T,
+    cs: &'a mut E,
+    finished: bool,
+}
+
+impl<'a, T: AsyncSpiBus, E: OutputPin> ChipSelectGuard<'a, T, E> {
+    pub fn new(spi: &'a mut T, cs: &'a mut E) -> Self {
+        cs.set_low().unwrap_or_default();
+        Self {
+            spi,
+            cs,
+            finished: false,
+        }
+    }
+    pub fn bus(&mut self) -> &mut T {
+        self.spi
+    }
+    /// Releases CS after a completed transfer
+    pub fn finish(mut self) {
+        self.finished = true;
+    }
+}
+
+impl<'a, T: AsyncSpiBus, E: OutputPin> Drop for ChipSelectGuard<'a, T, E> {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.spi.abort();
+        }
+        self.cs.set_high().unwrap_or_default();
+    }
+}
+
+/// Reads one byte. A timeout resets the peripheral.
+pub async fn async_read<T: FullDuplex<u8> + SpiInterrupt>(
+    spi: &mut T,
+    deadline: Option<TickTime>,
+) -> Result<u8, SpiError> {
+    let res = before(deadline, AsynSpiRead { spi: &mut *spi })
+        .await
+        .unwrap_or(Err(SpiError::Timeout));
+    if res == Err(SpiError::Timeout) {
+        spi.reset();
+    }
+    res
+}
+/// Writes one byte. A timeout resets the peripheral.
+pub async fn async_write<T: FullDuplex<u8> + SpiInterrupt>(
+    spi: &mut T,
+    payload: u8,
+    deadline: Option<TickTime>,
+) -> Result<(), SpiError> {
+    let res = before(
+        deadline,
+        AsynSpiWrite {
+            spi: &mut *spi,
+            payload,
+        },
+    )
+    .await
+    .unwrap_or(Err(SpiError::Timeout));
+    if res == Err(SpiError::Timeout) {
+        spi.reset();
+    }
+    res
+}
+/// Transfers the whole buffer byte by byte. The deadline applies to the whole transfer, a timeout resets the peripheral.
+pub async fn async_transfer<'a, T: FullDuplex<u8> + SpiInterrupt>(
     spi: &mut T,
     transfer_buffer: &'a mut [u8],
-) -> Result<&'a [u8], ()> {
+    deadline: Option<TickTime>,
+) -> Result<&'a [u8], SpiError> {
+    let res = before(deadline, transfer_bytes(&mut *spi, &mut *transfer_buffer))
+        .await
+        .unwrap_or(Err(SpiError::Timeout));
+    if res == Err(SpiError::Timeout) {
+        spi.reset();
+    }
+    res.map(|_| &*transfer_buffer)
+}
+async fn transfer_bytes<T: FullDuplex<u8> + SpiInterrupt>(
+    spi: &mut T,
+    transfer_buffer: &mut [u8],
+) -> Result<(), SpiError> {
     for byte in transfer_buffer.iter_mut() {
-        async_write(spi, *byte).await?;
-        *byte = async_read(spi).await?;
+        AsynSpiWrite {
+            spi: &mut *spi,
+            payload: *byte,
+        }
+        .await?;
+        *byte = AsynSpiRead { spi: &mut *spi }.await?;
     }
-    Ok(transfer_buffer)
+    Ok(())
 }
-struct AsynSpiRead<'a, T: FullDuplex<u8>> {
+struct AsynSpiRead<'a, T: FullDuplex<u8> + SpiInterrupt> {
     spi: &'a mut T,
 }
 
-impl<'a, T: FullDuplex<u8>> Future for AsynSpiRead<'a, T> {
-    type Output = Result<u8, ()>;
+impl<'a, T: FullDuplex<u8> + SpiInterrupt> Future for AsynSpiRead<'a, T> {
+    type Output = Result<u8, SpiError>;
 
     fn poll(
         mut self: core::pin::Pin<&mut Self>,
-        _cx: &mut core::task::Context<'_>,
+        cx: &mut core::task::Context<'_>,
     ) -> core::task::Poll<Self::Output> {
         let r = self.spi.read();
         match r {
             Ok(res) => Poll::Ready(Ok(res)),
-            Err(nb::Error::Other(_)) => Poll::Ready(Err(())),
+            Err(nb::Error::Other(_)) => Poll::Ready(Err(SpiError::Bus)),
             Err(nb::Error::WouldBlock) => {
-                return Poll::Pending;
+                self.spi.listen(SpiEvent::RxNotEmpty, cx.waker());
+                Poll::Pending
             }
         }
     }
 }
 
-struct AsynSpiWrite<'a, T: FullDuplex<u8>> {
+struct AsynSpiWrite<'a, T: FullDuplex<u8> + SpiInterrupt> {
     spi: &'a mut T,
     payload: u8,
 }
-impl<'a, T: FullDuplex<u8>> Future for AsynSpiWrite<'a, T> {
-    type Output = Result<(), ()>;
+impl<'a, T: FullDuplex<u8> + SpiInterrupt> Future for AsynSpiWrite<'a, T> {
+    type Output = Result<(), SpiError>;
 
     fn poll(
         mut self: core::pin::Pin<&mut Self>,
-        _cx: &mut core::task::Context<'_>,
+        cx: &mut core::task::Context<'_>,
     ) -> core::task::Poll<Self::Output> {
         let p = self.payload;
         let r = self.spi.send(p);
         match r {
             Ok(_) => Poll::Ready(Ok(())),
-            Err(nb::Error::Other(_)) => Poll::Ready(Err(())),
-            Err(nb::Error::WouldBlock) => Poll::Pending,
+            Err(nb::Error::Other(_)) => Poll::Ready(Err(SpiError::Bus)),
+            Err(nb::Error::WouldBlock) => {
+                self.spi.listen(SpiEvent::TxEmpty, cx.waker());
+                Poll::Pending
+            }
         }
     }
 }
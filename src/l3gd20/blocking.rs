@@ -0,0 +1,53 @@
+use super::registers::*;
+use super::temperature::Temperature;
+use super::{decode_values, read_command, write_command, VALUES_LEN};
+use embedded_hal::blocking::spi::Transfer;
+use embedded_hal::digital::v2::OutputPin;
+
+/// Blocking access to the L3GD20 built on [embedded_hal::blocking::spi]
+///
+/// Useful before the executor is started, e.g. for boot checks in `main`. Uses the same commands and decoding as the
+/// async [super::L3gd20]. Release the bus afterwards to hand it to the async driver.
+pub struct L3gd20Blocking<T: Transfer<u8>, E: OutputPin> {
+    spi: T,
+    cs: E,
+}
+
+impl<T: Transfer<u8>, E: OutputPin> L3gd20Blocking<T, E> {
+    pub fn new(spi: T, cs: E) -> Self {
+        Self { spi, cs }
+    }
+    pub fn release(self) -> (T, E) {
+        (self.spi, self.cs)
+    }
+    pub fn enable(&mut self) -> Result<(), T::Error> {
+        self.write_register(CTRL_REG1, 0b0000_1111)
+    }
+    /// Returns the content of WHO_AM_I which must be [DEVICE_ID]
+    pub fn who_am_i(&mut self) -> Result<u8, T::Error> {
+        self.read_register(WHO_AM_I)
+    }
+    pub fn read_register(&mut self, reg: u8) -> Result<u8, T::Error> {
+        let mut buf = [read_command(reg, 1), 0];
+        self.transfer(&mut buf)?;
+        Ok(buf[1])
+    }
+    pub fn write_register(&mut self, reg: u8, value: u8) -> Result<(), T::Error> {
+        let mut buf = [write_command(reg, 1), value];
+        self.transfer(&mut buf)
+    }
+    /// Reads the uncompensated angular rates of all axis and the temperature
+    pub fn read_raw(&mut self) -> Result<(i16, i16, i16, Temperature), T::Error> {
+        let mut buf = [0; VALUES_LEN + 1];
+        buf[0] = read_command(OUT_TEMP, VALUES_LEN);
+        self.transfer(&mut buf)?;
+        Ok(decode_values(&buf[1..]))
+    }
+
+    fn transfer(&mut self, buf: &mut [u8]) -> Result<(), T::Error> {
+        self.cs.set_low().unwrap_or_default();
+        let res = self.spi.transfer(buf).map(|_| ());
+        self.cs.set_high().unwrap_or_default();
+        res
+    }
+}
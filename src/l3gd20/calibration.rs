@@ -0,0 +1,235 @@
+use super::async_spi::SpiError;
+
+/// Zero-rate offset of every axis in raw counts
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Bias {
+    pub x: i16,
+    pub y: i16,
+    pub z: i16,
+}
+
+impl Bias {
+    /// Removes the offset from a raw sample
+    pub fn apply(&self, x: i16, y: i16, z: i16) -> (i16, i16, i16) {
+        (
+            x.saturating_sub(self.x),
+            y.saturating_sub(self.y),
+            z.saturating_sub(self.z),
+        )
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum CalibrationError {
+    /// The device moved while collecting the samples
+    NotStationary,
+    /// Zero samples were requested
+    NoSamples,
+    /// Reading a sample failed
+    Spi(SpiError),
+}
+
+impl From<SpiError> for CalibrationError {
+    fn from(error: SpiError) -> Self {
+        CalibrationError::Spi(error)
+    }
+}
+
+/// Collects raw samples and detects if the device was stationary while they were taken
+///
+/// The device is considered stationary if no axis spreads more than `threshold` counts between its min and max value
+pub struct SampleWindow {
+    threshold: u16,
+    cnt: usize,
+    sum: [i32; 3],
+    min: [i16; 3],
+    max: [i16; 3],
+}
+
+impl SampleWindow {
+    pub fn new(threshold: u16) -> Self {
+        Self {
+            threshold,
+            cnt: 0,
+            sum: [0; 3],
+            min: [i16::MAX; 3],
+            max: [i16::MIN; 3],
+        }
+    }
+    pub fn add(&mut self, sample: [i16; 3]) {
+        for (i, value) in sample.iter().enumerate() {
+            self.sum[i] += *value as i32;
+            self.min[i] = self.min[i].min(*value);
+            self.max[i] = self.max[i].max(*value);
+        }
+        self.cnt += 1;
+    }
+    pub fn len(&self) -> usize {
+        self.cnt
+    }
+    pub fn is_empty(&self) -> bool {
+        self.cnt == 0
+    }
+    pub fn clear(&mut self) {
+        *self = Self::new(self.threshold);
+    }
+    /// Returns if all collected samples stayed within the threshold
+    pub fn is_still(&self) -> bool {
+        !self.is_empty()
+            && (0..3).all(|i| (self.max[i] as i32 - self.min[i] as i32) <= self.threshold as i32)
+    }
+    /// Rounded mean of all samples
+    pub fn mean(&self) -> Bias {
+        let n = self.cnt.max(1) as i32;
+        Bias {
+            x: div_round(self.sum[0], n) as i16,
+            y: div_round(self.sum[1], n) as i16,
+            z: div_round(self.sum[2], n) as i16,
+        }
+    }
+    /// Mean of the window or an error if the device was not stationary
+    pub fn bias(&self) -> Result<Bias, CalibrationError> {
+        if self.is_empty() {
+            Err(CalibrationError::NoSamples)
+        } else if !self.is_still() {
+            Err(CalibrationError::NotStationary)
+        } else {
+            Ok(self.mean())
+        }
+    }
+}
+
+/// Refines a bias in the background whenever a full window of samples was taken while the device was still
+///
+/// Every still window moves the bias a quarter of the way to the mean of that window so single windows with a
+/// slow rotation below the threshold only have a small effect.
+pub struct BackgroundCalibration {
+    window: SampleWindow,
+    window_len: usize,
+}
+
+impl BackgroundCalibration {
+    pub fn new(window_len: usize, threshold: u16) -> Self {
+        Self {
+            window: SampleWindow::new(threshold),
+            window_len: window_len.max(1),
+        }
+    }
+    /// Feeds a raw (uncompensated) sample. Returns true if the bias was refined.
+    pub fn update(&mut self, bias: &mut Bias, sample: [i16; 3]) -> bool {
+        self.window.add(sample);
+        if self.window.len() < self.window_len {
+            return false;
+        }
+        let refined = if let Ok(mean) = self.window.bias() {
+            bias.x = blend(bias.x, mean.x);
+            bias.y = blend(bias.y, mean.y);
+            bias.z = blend(bias.z, mean.z);
+            true
+        } else {
+            false
+        };
+        self.window.clear();
+        refined
+    }
+}
+
+fn blend(old: i16, new: i16) -> i16 {
+    let blended = div_round(3 * old as i32 + new as i32, 4) as i16;
+    if blended == old {
+        // a quarter of a difference below 2 counts would be rounded away
+        old + (new - old).signum()
+    } else {
+        blended
+    }
+}
+
+fn div_round(value: i32, divisor: i32) -> i32 {
+    if value >= 0 {
+        (value + divisor / 2) / divisor
+    } else {
+        (value - divisor / 2) / divisor
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bias_of_still_device() {
+        let mut window = SampleWindow::new(10);
+        window.add([20, -30, 5]);
+        window.add([24, -33, 6]);
+        window.add([22, -31, 4]);
+        assert_eq!(
+            window.bias(),
+            Ok(Bias {
+                x: 22,
+                y: -31,
+                z: 5
+            })
+        );
+    }
+
+    #[test]
+    fn moving_device_is_rejected() {
+        let mut window = SampleWindow::new(10);
+        assert_eq!(window.bias(), Err(CalibrationError::NoSamples));
+        window.add([20, -30, 5]);
+        window.add([20, 300, 5]);
+        assert_eq!(window.bias(), Err(CalibrationError::NotStationary));
+    }
+
+    #[test]
+    fn apply_saturates() {
+        let bias = Bias {
+            x: 10,
+            y: -10,
+            z: 0,
+        };
+        assert_eq!(bias.apply(i16::MIN, i16::MAX, 7), (i16::MIN, i16::MAX, 7));
+        assert_eq!(bias.apply(15, -15, 0), (5, -5, 0));
+    }
+
+    #[test]
+    fn background_refines_only_when_still() {
+        let mut bias = Bias::default();
+        let mut background = BackgroundCalibration::new(2, 5);
+        assert!(!background.update(&mut bias, [40, 0, -40]));
+        assert!(background.update(&mut bias, [40, 0, -40]));
+        assert_eq!(
+            bias,
+            Bias {
+                x: 10,
+                y: 0,
+                z: -10
+            }
+        );
+
+        // moving window does not change the bias
+        background.update(&mut bias, [40, 0, -40]);
+        assert!(!background.update(&mut bias, [400, 0, -40]));
+        assert_eq!(
+            bias,
+            Bias {
+                x: 10,
+                y: 0,
+                z: -10
+            }
+        );
+
+        // converges to the true offset
+        for _ in 0..40 {
+            background.update(&mut bias, [40, 0, -40]);
+        }
+        assert_eq!(
+            bias,
+            Bias {
+                x: 40,
+                y: 0,
+                z: -40
+            }
+        );
+    }
+}
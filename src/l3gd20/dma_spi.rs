@@ -0,0 +1,146 @@
+use super::async_spi::{AsyncSpiBus, SpiError, SpiInterrupt};
+use crate::deadline::before;
+use crate::dma::{configure, CCR_IRQ_MASK, MAX_TRANSFER};
+use core::future::Future;
+use core::task::Poll;
+use cortex_m::peripheral::NVIC;
+use lilos::exec::Notify;
+use lilos::time::TickTime;
+use stm32f3xx_hal::dma::{dma1, Channel, Direction, Event};
+use stm32f3xx_hal::pac::{interrupt, Interrupt, DMA1, SPI1};
+use stm32f3xx_hal::spi::Spi;
+
+static DMA1_CH2_NOTIFY: Notify = Notify::new();
+
+/// SPI1 with whole buffer transfers done by DMA1
+///
+/// Channel 2 receives (SPI1_RX) and Channel 3 sends (SPI1_TX). The task is woken once by the transfer complete (or
+/// error) interrupt of the receive channel, which finishes last. Buffers over [MAX_TRANSFER] bytes are transferred in
+/// several DMA transfers.
+pub struct DmaSpi<Pins> {
+    spi: Spi<SPI1, Pins, u8>,
+    rx: dma1::C2,
+    tx: dma1::C3,
+}
+
+impl<Pins> DmaSpi<Pins> {
+    pub fn new(spi: Spi<SPI1, Pins, u8>, rx: dma1::C2, tx: dma1::C3) -> Self {
+        unsafe { NVIC::unmask(Interrupt::DMA1_CH2) };
+        Self { spi, rx, tx }
+    }
+    pub(crate) fn spi_mut(&mut self) -> &mut Spi<SPI1, Pins, u8> {
+        &mut self.spi
+    }
+    pub fn free(self) -> (Spi<SPI1, Pins, u8>, dma1::C2, dma1::C3) {
+        (self.spi, self.rx, self.tx)
+    }
+
+    fn start(&mut self, buf: &mut [u8]) {
+        let spi = unsafe { &*SPI1::ptr() };
+        let dr = &spi.dr as *const _ as u32;
+        let mem = buf.as_mut_ptr() as u32;
+        // drop stale bytes of previous byte wise accesses
+        while spi.sr.read().rxne().bit_is_set() {
+            let _ = spi.dr.read();
+        }
+
+        configure(&mut self.rx, dr, mem, buf.len(), Direction::FromPeripheral);
+        configure(&mut self.tx, dr, mem, buf.len(), Direction::FromMemory);
+        // Order as required by the reference manual: RX DMA request, channels, TX DMA request
+        spi.cr2.modify(|_, w| w.rxdmaen().set_bit());
+        self.rx.enable();
+        self.tx.enable();
+        spi.cr2.modify(|_, w| w.txdmaen().set_bit());
+    }
+    fn stop(&mut self) {
+        let spi = unsafe { &*SPI1::ptr() };
+        spi.cr2
+            .modify(|_, w| w.txdmaen().clear_bit().rxdmaen().clear_bit());
+        self.tx.disable();
+        self.rx.disable();
+        self.tx.clear_event(Event::Any);
+        self.rx.clear_event(Event::Any);
+    }
+}
+
+impl<Pins> AsyncSpiBus for DmaSpi<Pins> {
+    fn async_transfer<'a>(
+        &'a mut self,
+        transfer_buffer: &'a mut [u8],
+        deadline: Option<TickTime>,
+    ) -> impl Future<Output = Result<&'a [u8], SpiError>> + 'a {
+        async move {
+            for chunk in transfer_buffer.chunks_mut(MAX_TRANSFER) {
+                self.start(chunk);
+                let transfer = DmaTransfer {
+                    spi: &mut *self,
+                    done: false,
+                };
+                let res = before(deadline, transfer)
+                    .await
+                    .unwrap_or(Err(SpiError::Timeout));
+                if res == Err(SpiError::Timeout) {
+                    self.abort();
+                }
+                res?;
+            }
+            Ok(&*transfer_buffer)
+        }
+    }
+    fn abort(&mut self) {
+        self.stop();
+        self.spi.reset();
+    }
+}
+
+/// Waits for the end of the transfer started by [DmaSpi::start]
+///
+/// Stops the DMA when dropped so the buffer is not written after its borrow ended.
+struct DmaTransfer<'a, Pins> {
+    spi: &'a mut DmaSpi<Pins>,
+    done: bool,
+}
+
+impl<'a, Pins> Future for DmaTransfer<'a, Pins> {
+    type Output = Result<(), SpiError>;
+
+    fn poll(
+        mut self: core::pin::Pin<&mut Self>,
+        cx: &mut core::task::Context<'_>,
+    ) -> core::task::Poll<Self::Output> {
+        let rx = &self.spi.rx;
+        let res = if rx.is_event_triggered(Event::TransferError)
+            || self.spi.tx.is_event_triggered(Event::TransferError)
+        {
+            Err(SpiError::Bus)
+        } else if rx.is_event_triggered(Event::TransferComplete) {
+            Ok(())
+        } else {
+            DMA1_CH2_NOTIFY.subscribe(cx.waker());
+            self.spi.rx.enable_interrupt(Event::TransferComplete);
+            self.spi.rx.enable_interrupt(Event::TransferError);
+            return Poll::Pending;
+        };
+        self.done = true;
+        self.spi.stop();
+        Poll::Ready(res)
+    }
+}
+
+impl<'a, Pins> Drop for DmaTransfer<'a, Pins> {
+    fn drop(&mut self) {
+        if !self.done {
+            self.spi.stop();
+        }
+    }
+}
+
+#[interrupt]
+fn DMA1_CH2() {
+    let dma = unsafe { &*DMA1::ptr() };
+    // flags stay set for the waiting future - only mask the interrupts
+    dma.ch2
+        .cr
+        .modify(|r, w| unsafe { w.bits(r.bits() & !CCR_IRQ_MASK) });
+    DMA1_CH2_NOTIFY.notify();
+}
@@ -0,0 +1,145 @@
+use super::async_spi::{AsyncSpiBus, ChipSelectGuard, ExclusiveSpiDevice, SpiError};
+use super::shared_spi::{SharedSpiDevice, SpiConfigure};
+use core::time::Duration;
+use embedded_hal::digital::v2::OutputPin;
+use embedded_hal_async::spi::{Error, ErrorKind, ErrorType, Operation, SpiBus, SpiDevice};
+
+/// Bytes transferred at once by [SpiBus::write] and [SpiBus::transfer], which cannot work in place
+const CHUNK: usize = 16;
+/// Sent by [SpiBus::read] and to pad [SpiBus::transfer]
+const FILL: u8 = 0x00;
+
+/// embedded-hal has no kind for a timeout and the peripheral error is not known anymore, drivers which need to tell
+/// them apart match on the [SpiError] itself
+impl Error for SpiError {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            SpiError::Bus => ErrorKind::Other,
+            SpiError::Timeout => ErrorKind::Other,
+        }
+    }
+}
+
+/// [SpiBus] on top of an [AsyncSpiBus]
+///
+/// [stm32f3xx_hal::spi::Spi] is not our type, so the buses are wrapped instead of implementing [SpiBus] directly. The
+/// transfers have no deadline.
+pub struct HalSpiBus<B> {
+    bus: B,
+}
+
+impl<B: AsyncSpiBus> HalSpiBus<B> {
+    pub fn new(bus: B) -> Self {
+        Self { bus }
+    }
+    pub fn release(self) -> B {
+        self.bus
+    }
+}
+
+impl<B: AsyncSpiBus> ErrorType for HalSpiBus<B> {
+    type Error = SpiError;
+}
+
+impl<B: AsyncSpiBus> SpiBus for HalSpiBus<B> {
+    async fn read(&mut self, words: &mut [u8]) -> Result<(), SpiError> {
+        words.fill(FILL);
+        self.bus.async_transfer(words, None).await?;
+        Ok(())
+    }
+    async fn write(&mut self, words: &[u8]) -> Result<(), SpiError> {
+        transfer(&mut self.bus, &mut [], words).await
+    }
+    async fn transfer(&mut self, read: &mut [u8], write: &[u8]) -> Result<(), SpiError> {
+        transfer(&mut self.bus, read, write).await
+    }
+    async fn transfer_in_place(&mut self, words: &mut [u8]) -> Result<(), SpiError> {
+        self.bus.async_transfer(words, None).await?;
+        Ok(())
+    }
+    /// Transfers are finished when their future completes
+    async fn flush(&mut self) -> Result<(), SpiError> {
+        Ok(())
+    }
+}
+
+/// Transfers `max(read.len(), write.len())` bytes through a buffer on the stack
+///
+/// Missing write bytes are sent as [FILL], surplus read bytes are dropped.
+async fn transfer<B: AsyncSpiBus>(
+    bus: &mut B,
+    read: &mut [u8],
+    write: &[u8],
+) -> Result<(), SpiError> {
+    let len = read.len().max(write.len());
+    let mut chunk = [FILL; CHUNK];
+    for start in (0..len).step_by(CHUNK) {
+        let n = CHUNK.min(len - start);
+        for (i, byte) in chunk[..n].iter_mut().enumerate() {
+            *byte = write.get(start + i).copied().unwrap_or(FILL);
+        }
+        let received = bus.async_transfer(&mut chunk[..n], None).await?;
+        if start < read.len() {
+            let end = read.len().min(start + n);
+            read[start..end].copy_from_slice(&received[..end - start]);
+        }
+    }
+    Ok(())
+}
+
+/// Runs one operation of a [SpiDevice::transaction] while CS is asserted
+async fn run<B: AsyncSpiBus>(
+    bus: &mut B,
+    operation: &mut Operation<'_, u8>,
+) -> Result<(), SpiError> {
+    match operation {
+        Operation::Read(words) => {
+            words.fill(FILL);
+            bus.async_transfer(words, None).await?;
+        }
+        Operation::Write(words) => transfer(bus, &mut [], words).await?,
+        Operation::Transfer(read, write) => transfer(bus, read, write).await?,
+        Operation::TransferInPlace(words) => {
+            bus.async_transfer(words, None).await?;
+        }
+        // lilos sleeps in whole milliseconds
+        Operation::DelayNs(ns) => {
+            let ms = (*ns as u64).div_ceil(1_000_000);
+            lilos::exec::sleep_for(Duration::from_millis(ms)).await
+        }
+    }
+    Ok(())
+}
+
+impl<T: AsyncSpiBus, E: OutputPin> ErrorType for ExclusiveSpiDevice<T, E> {
+    type Error = SpiError;
+}
+
+impl<T: AsyncSpiBus, E: OutputPin> SpiDevice for ExclusiveSpiDevice<T, E> {
+    async fn transaction(&mut self, operations: &mut [Operation<'_, u8>]) -> Result<(), SpiError> {
+        let (spi, cs) = self.parts();
+        let mut guard = ChipSelectGuard::new(spi, cs);
+        for operation in operations.iter_mut() {
+            run(guard.bus(), operation).await?;
+        }
+        guard.finish();
+        Ok(())
+    }
+}
+
+impl<'a, B: AsyncSpiBus + SpiConfigure, E: OutputPin> ErrorType for SharedSpiDevice<'a, B, E> {
+    type Error = SpiError;
+}
+
+/// Locks the bus for the whole transaction
+impl<'a, B: AsyncSpiBus + SpiConfigure, E: OutputPin> SpiDevice for SharedSpiDevice<'a, B, E> {
+    async fn transaction(&mut self, operations: &mut [Operation<'_, u8>]) -> Result<(), SpiError> {
+        let (mut bus, cs) = self.lock(None).await?;
+        let mut guard = ChipSelectGuard::new(&mut *bus, cs);
+        for operation in operations.iter_mut() {
+            run(guard.bus(), operation).await?;
+        }
+        guard.finish();
+        Ok(())
+    }
+}
@@ -0,0 +1,153 @@
+use crate::cycle_clock::cycles;
+use core::future::Future;
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use core::task::Poll;
+use embedded_hal::digital::v2::InputPin;
+use lilos::exec::Notify;
+use stm32f3xx_hal::pac::{interrupt, EXTI};
+
+/// Interrupt outputs of the L3GD20 and the EXTI Line they are wired to on the Discovery Board
+#[derive(Clone, Copy, PartialEq)]
+pub enum Line {
+    /// INT1 on PE0 => EXTI0
+    Int1,
+    /// DRDY/INT2 on PE1 => EXTI1
+    Int2,
+}
+
+static INT1_NOTIFY: Notify = Notify::new();
+static INT2_NOTIFY: Notify = Notify::new();
+/// Cycle count of the last edge and if the ISR took it
+static INT1_EDGE: AtomicU32 = AtomicU32::new(0);
+static INT2_EDGE: AtomicU32 = AtomicU32::new(0);
+static INT1_CAPTURED: AtomicBool = AtomicBool::new(false);
+static INT2_CAPTURED: AtomicBool = AtomicBool::new(false);
+
+impl Line {
+    fn exti_mask(self) -> u32 {
+        match self {
+            Line::Int1 => 1 << 0,
+            Line::Int2 => 1 << 1,
+        }
+    }
+    fn notify(self) -> &'static Notify {
+        match self {
+            Line::Int1 => &INT1_NOTIFY,
+            Line::Int2 => &INT2_NOTIFY,
+        }
+    }
+    fn edge(self) -> &'static AtomicU32 {
+        match self {
+            Line::Int1 => &INT1_EDGE,
+            Line::Int2 => &INT2_EDGE,
+        }
+    }
+    fn captured(self) -> &'static AtomicBool {
+        match self {
+            Line::Int1 => &INT1_CAPTURED,
+            Line::Int2 => &INT2_CAPTURED,
+        }
+    }
+}
+
+/// Interrupt Pin of the L3GD20 backed by a EXTI Line
+///
+/// The Pin must already be configured as EXTI source with a rising edge trigger and the EXTI interrupt must be unmasked in the NVIC.
+/// The EXTI Line itself is only armed while a task is waiting on it. The ISR masks it again and wakes the waiting task.
+pub struct IntLine<P: InputPin> {
+    pin: P,
+    line: Line,
+}
+
+impl<P: InputPin> IntLine<P> {
+    pub fn new(pin: P, line: Line) -> Self {
+        Self { pin, line }
+    }
+    /// Returns if the interrupt output of the L3GD20 is currently active (active high is the reset default)
+    pub fn is_active(&self) -> bool {
+        self.pin.is_high().unwrap_or_default()
+    }
+    /// Waits until the interrupt output is active
+    ///
+    /// Returns immediately if the pin is already active. The L3GD20 outputs are level based (e.g. DRDY stays high until
+    /// the data is read) so no edge can be lost between two calls.
+    pub async fn wait_for_active(&self) {
+        WaitForActive {
+            int: self,
+            armed: false,
+        }
+        .await
+    }
+    /// [cycles] at which the last [IntLine::wait_for_active] saw the output become active
+    ///
+    /// This is the time of the rising edge taken in the ISR. If the output was already active when waiting started
+    /// the time the wait returned is used instead, the edge itself happened earlier.
+    pub fn edge_cycles(&self) -> u32 {
+        self.line.edge().load(Ordering::Relaxed)
+    }
+}
+
+struct WaitForActive<'a, P: InputPin> {
+    int: &'a IntLine<P>,
+    armed: bool,
+}
+
+impl<'a, P: InputPin> WaitForActive<'a, P> {
+    /// Keeps the edge time of the ISR if it fired while the Line was armed, otherwise takes the current time
+    fn take_edge(&self) {
+        let line = self.int.line;
+        if !(self.armed && line.captured().swap(false, Ordering::Relaxed)) {
+            line.edge().store(cycles(), Ordering::Relaxed);
+        }
+    }
+}
+
+impl<'a, P: InputPin> Future for WaitForActive<'a, P> {
+    type Output = ();
+
+    fn poll(
+        mut self: core::pin::Pin<&mut Self>,
+        cx: &mut core::task::Context<'_>,
+    ) -> core::task::Poll<Self::Output> {
+        if self.int.is_active() {
+            self.take_edge();
+            return Poll::Ready(());
+        }
+        let line = self.int.line;
+        line.notify().subscribe(cx.waker());
+        // arm the EXTI Line - the ISR masks it again
+        line.captured().store(false, Ordering::Relaxed);
+        self.armed = true;
+        let exti = unsafe { &*EXTI::ptr() };
+        exti.pr1.write(|w| unsafe { w.bits(line.exti_mask()) });
+        exti.imr1
+            .modify(|r, w| unsafe { w.bits(r.bits() | line.exti_mask()) });
+        // the edge may have happened before the Line was armed
+        if self.int.is_active() {
+            self.take_edge();
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+fn on_exti(line: Line) {
+    line.edge().store(cycles(), Ordering::Relaxed);
+    line.captured().store(true, Ordering::Relaxed);
+    let exti = unsafe { &*EXTI::ptr() };
+    exti.imr1
+        .modify(|r, w| unsafe { w.bits(r.bits() & !line.exti_mask()) });
+    exti.pr1.write(|w| unsafe { w.bits(line.exti_mask()) });
+    line.notify().notify();
+}
+
+#[interrupt]
+fn EXTI0() {
+    on_exti(Line::Int1);
+}
+
+#[interrupt]
+fn EXTI1() {
+    on_exti(Line::Int2);
+}
@@ -1,51 +1,510 @@
 pub mod async_spi;
+/// Blocking front-end for use outside of the executor
+pub mod blocking;
+/// Zero-rate offset calibration
+pub mod calibration;
+/// DMA backed SPI1 transfers
+pub mod dma_spi;
+/// [embedded_hal_async::spi::SpiBus] and [embedded_hal_async::spi::SpiDevice] for the async SPI buses
+pub mod hal_async;
+/// Interrupt Lines of the L3GD20 backed by EXTI
+pub mod int_line;
+/// Motion threshold interrupt generator on INT1
+pub mod motion;
+/// Integration of angular rates into an orientation
+pub mod orientation;
+/// Register addresses and bit definitions
+pub mod registers;
+/// Evaluation of the built-in self-test
+pub mod self_test;
+/// SPI bus shared by several devices
+pub mod shared_spi;
+/// Temperature readout and temperature compensated bias
+pub mod temperature;
 
-use embedded_hal::digital::v2::OutputPin;
-use embedded_hal::spi::FullDuplex;
+use crate::cycle_clock::CycleClock;
+use async_spi::{AsyncSpiBus, AsyncSpiDevice, ExclusiveSpiDevice, SpiError};
+use calibration::{BackgroundCalibration, Bias, CalibrationError, SampleWindow};
+use core::time::Duration;
+use embedded_hal::digital::v2::{InputPin, OutputPin};
+use int_line::IntLine;
+use lilos::time::TickTime;
+use motion::{MotionConfig, MotionSource};
+use orientation::{Orientation, RateSample};
+use registers::*;
+use self_test::{SelfTestLimits, SelfTestReport};
+use temperature::{Temperature, TemperatureBiasFit, TemperatureBiasModel};
 
-const CMD: u8 = 224; // [Bit 7 = 1 (Read), Bit 6 = 1 (increment adr), Bit 5..0 = 0x20]
-const WRITE_CMD: u8 = 0x20;
+/// First byte of a transfer: [Bit 7 = 1 (Read), Bit 6 = 1 (increment adr), Bit 5..0 = register address]
+const READ_BIT: u8 = 1 << 7;
+const AUTO_INCREMENT_BIT: u8 = 1 << 6;
+/// The 6 bit address space limits a burst to 64 registers
+const MAX_BURST: usize = 64;
+const ADDRESS_MASK: u8 = 0b11_1111;
+/// OUT_TEMP, STATUS_REG and OUT_X_L..OUT_Z_H
+const VALUES_LEN: usize = 8;
+const SELF_TEST_SAMPLES: usize = 5;
+/// Settling time of the outputs after the self-test or the output data rate changed
+const SELF_TEST_SETTLE: Duration = Duration::from_millis(100);
 
-pub struct L3gd20<T: FullDuplex<u8>, E: OutputPin> {
-    spi: T,
-    cs: E,
+/// Event signaled on the DRDY/INT2 Pin
+#[derive(Clone, Copy, PartialEq)]
+pub enum DataReadyMode {
+    /// Signal every new sample
+    DataReady,
+    /// Buffer samples in the FIFO (stream mode) and signal when the given level (max. 31) is reached
+    Watermark(u8),
 }
-impl<T: FullDuplex<u8>, E: OutputPin> L3gd20<T, E> {
+
+/// Command byte reading `len` registers starting at `start`
+fn read_command(start: u8, len: usize) -> u8 {
+    if len > 1 {
+        READ_BIT | AUTO_INCREMENT_BIT | start
+    } else {
+        READ_BIT | start
+    }
+}
+
+/// Command byte writing `len` registers starting at `start`
+fn write_command(start: u8, len: usize) -> u8 {
+    if len > 1 {
+        AUTO_INCREMENT_BIT | start
+    } else {
+        start
+    }
+}
+
+/// Decodes the [VALUES_LEN] registers starting at OUT_TEMP
+///
+/// Shared by the async driver and the [blocking::L3gd20Blocking] front-end.
+fn decode_values(regs: &[u8]) -> (i16, i16, i16, Temperature) {
+    let x = i16::from_le_bytes([regs[2], regs[3]]);
+    let y = i16::from_le_bytes([regs[4], regs[5]]);
+    let z = i16::from_le_bytes([regs[6], regs[7]]);
+    (x, y, z, Temperature::from_raw(regs[0]))
+}
+
+/// Measurement range of the angular rates
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum FullScale {
+    Dps250,
+    Dps500,
+    Dps2000,
+}
+
+impl FullScale {
+    /// Angular rate of one count in °/s
+    pub fn sensitivity(self) -> f32 {
+        match self {
+            FullScale::Dps250 => 0.00875,
+            FullScale::Dps500 => 0.0175,
+            FullScale::Dps2000 => 0.070,
+        }
+    }
+    fn ctrl_reg4(self) -> u8 {
+        match self {
+            FullScale::Dps250 => 0b00 << 4,
+            FullScale::Dps500 => 0b01 << 4,
+            FullScale::Dps2000 => 0b10 << 4,
+        }
+    }
+}
+
+pub struct L3gd20<D: AsyncSpiDevice> {
+    device: D,
+    full_scale: FullScale,
+    bias: Bias,
+    background: Option<BackgroundCalibration>,
+    temperature_model: Option<TemperatureBiasModel>,
+    timeout: Option<Duration>,
+}
+impl<T: AsyncSpiBus, E: OutputPin> L3gd20<ExclusiveSpiDevice<T, E>> {
+    /// Driver owning the whole SPI bus
     pub fn new(spi: T, cs: E) -> Self {
-        Self { spi, cs }
+        Self::with_device(ExclusiveSpiDevice::new(spi, cs))
     }
+}
 
-    pub async fn enable(&mut self) {
-        self.select_device();
-        let mut enable = [WRITE_CMD, 0b0000_1111];
-        async_spi::async_transfer(&mut self.spi, &mut enable)
-            .await
-            .unwrap();
-        self.deselect_device()
-    }
-    pub async fn read_values(&mut self) -> (i16, i16, i16, i8) {
-        let mut buf = [0; 14];
-        self.select_device();
-        // write Reg Addr
-        async_spi::async_write(&mut self.spi, CMD).await.unwrap();
-        // clear Read Register
-        let _ = async_spi::async_read(&mut self.spi).await.unwrap();
-        // Read Registers
-        let l3gd20 = async_spi::async_transfer(&mut self.spi, &mut buf)
+impl<D: AsyncSpiDevice> L3gd20<D> {
+    /// Driver on a device handle, e.g. of a [shared_spi::SharedSpiBus]
+    pub fn with_device(device: D) -> Self {
+        Self {
+            device,
+            full_scale: FullScale::Dps250,
+            bias: Bias::default(),
+            background: None,
+            temperature_model: None,
+            timeout: None,
+        }
+    }
+    /// Fails every register access which takes longer than `timeout` with [SpiError::Timeout]
+    ///
+    /// Without a timeout (the default) an access waits as long as the bus does, e.g. for a shared bus held by another
+    /// task.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+    }
+
+    pub async fn enable(&mut self) -> Result<(), SpiError> {
+        self.write_register(CTRL_REG1, 0b0000_1111).await
+    }
+    /// Reads the angular rates of all axis with the bias removed and the temperature
+    ///
+    /// With a [TemperatureBiasModel] set the bias is taken from the model at the current temperature.
+    /// Otherwise the static bias is used and, if a [BackgroundCalibration] is active, the raw rates are fed to it first.
+    pub async fn read_values(&mut self) -> Result<(i16, i16, i16, Temperature), SpiError> {
+        let (x, y, z, temp) = self.read_raw().await?;
+        let bias = if let Some(model) = self.temperature_model.as_ref() {
+            model.bias_at(temp)
+        } else {
+            if let Some(background) = self.background.as_mut() {
+                background.update(&mut self.bias, [x, y, z]);
+            }
+            self.bias
+        };
+        let (x, y, z) = bias.apply(x, y, z);
+        Ok((x, y, z, temp))
+    }
+    /// Reads the uncompensated angular rates of all axis and the temperature
+    pub async fn read_raw(&mut self) -> Result<(i16, i16, i16, Temperature), SpiError> {
+        let mut regs = [0; VALUES_LEN];
+        self.read_registers(OUT_TEMP, &mut regs).await?;
+        Ok(decode_values(&regs))
+    }
+    pub async fn read_register(&mut self, reg: u8) -> Result<u8, SpiError> {
+        let mut buf = [read_command(reg, 1), 0];
+        let deadline = self.deadline();
+        self.device.transaction(&mut buf, deadline).await?;
+        Ok(buf[1])
+    }
+    /// Reads `buf.len()` consecutive registers starting at `start`
+    ///
+    /// Up to 64 registers are read in one transaction, longer reads are split into several transactions and wrap
+    /// around at the end of the address space like the auto increment does.
+    pub async fn read_registers(&mut self, start: u8, buf: &mut [u8]) -> Result<(), SpiError> {
+        let deadline = self.deadline();
+        let mut start = start;
+        for chunk in buf.chunks_mut(MAX_BURST) {
+            let mut transfer = [0; MAX_BURST + 1];
+            let transfer = &mut transfer[..=chunk.len()];
+            transfer[0] = read_command(start, chunk.len());
+            let regs = self.device.transaction(transfer, deadline).await?;
+            chunk.copy_from_slice(&regs[1..]);
+            start = start.wrapping_add(chunk.len() as u8) & ADDRESS_MASK;
+        }
+        Ok(())
+    }
+    pub async fn write_register(&mut self, reg: u8, value: u8) -> Result<(), SpiError> {
+        let mut buf = [write_command(reg, 1), value];
+        let deadline = self.deadline();
+        self.device.transaction(&mut buf, deadline).await?;
+        Ok(())
+    }
+    /// Writes consecutive registers starting at `start`
+    ///
+    /// Like [L3gd20::read_registers] writes longer than 64 registers are split into several transactions.
+    pub async fn write_registers(&mut self, start: u8, values: &[u8]) -> Result<(), SpiError> {
+        let deadline = self.deadline();
+        let mut start = start;
+        for chunk in values.chunks(MAX_BURST) {
+            let mut transfer = [0; MAX_BURST + 1];
+            let transfer = &mut transfer[..=chunk.len()];
+            transfer[0] = write_command(start, chunk.len());
+            transfer[1..].copy_from_slice(chunk);
+            self.device.transaction(transfer, deadline).await?;
+            start = start.wrapping_add(chunk.len() as u8) & ADDRESS_MASK;
+        }
+        Ok(())
+    }
+    /// Replaces the bits of `mask` in `reg` with those of `value` (read-modify-write)
+    pub async fn modify_register(&mut self, reg: u8, mask: u8, value: u8) -> Result<(), SpiError> {
+        let old = self.read_register(reg).await?;
+        self.write_register(reg, (old & !mask) | (value & mask))
             .await
-            .unwrap();
-        self.deselect_device();
+    }
+    /// Selects the measurement range
+    ///
+    /// The bias is kept in counts: calibrate again after changing the range.
+    pub async fn set_full_scale(&mut self, full_scale: FullScale) -> Result<(), SpiError> {
+        self.modify_register(CTRL_REG4, CTRL4_FS_MASK, full_scale.ctrl_reg4())
+            .await?;
+        self.full_scale = full_scale;
+        Ok(())
+    }
+    pub fn full_scale(&self) -> FullScale {
+        self.full_scale
+    }
+    /// Reads the calibrated angular rates in °/s time stamped with the current system time
+    ///
+    /// The time stamp is taken from the 1ms [TickTime] before the transfer, so it lags the sample by the latency of the
+    /// caller and is only exact to ±1ms (±10% of the sample period at 95Hz). Use [L3gd20::wait_for_sample] to time stamp
+    /// the sample at data ready with µs resolution.
+    pub async fn read_sample(&mut self) -> Result<RateSample, SpiError> {
+        let timestamp_ms = u64::from(TickTime::now());
+        let (x, y, z, _temp) = self.read_values().await?;
+        Ok(self.rate_sample(timestamp_ms * 1000, x, y, z))
+    }
+    /// Waits for the next sample and reads it time stamped with the data ready edge of the DRDY/INT2 Pin
+    ///
+    /// Requires [DataReadyMode::DataReady] (see [L3gd20::enable_data_ready_interrupt]). If the sample was ready before
+    /// the call the time stamp is the time of the call instead of the edge.
+    pub async fn wait_for_sample<P: InputPin>(
+        &mut self,
+        int2: &IntLine<P>,
+        clock: &CycleClock,
+    ) -> Result<RateSample, SpiError> {
+        self.wait_for_data(int2).await;
+        self.read_sample_at_edge(int2, clock).await
+    }
+    /// Reads the sample signalled by the last [IntLine::wait_for_active] of `int2`, time stamped with its edge
+    ///
+    /// Same as [L3gd20::wait_for_sample] with the wait left to the caller, so a driver shared in a
+    /// [crate::mutex::Mutex] is only locked for the read and not while waiting for the edge.
+    pub async fn read_sample_at_edge<P: InputPin>(
+        &mut self,
+        int2: &IntLine<P>,
+        clock: &CycleClock,
+    ) -> Result<RateSample, SpiError> {
+        let timestamp_us = clock.to_us(int2.edge_cycles());
+        let (x, y, z, _temp) = self.read_values().await?;
+        Ok(self.rate_sample(timestamp_us, x, y, z))
+    }
+    /// Waits for the next sample (see [L3gd20::wait_for_sample]) and integrates it into `orientation`
+    pub async fn update_orientation<P: InputPin>(
+        &mut self,
+        int2: &IntLine<P>,
+        clock: &CycleClock,
+        orientation: &mut Orientation,
+    ) -> Result<(), SpiError> {
+        let sample = self.wait_for_sample(int2, clock).await?;
+        orientation.update(&sample);
+        Ok(())
+    }
+    /// Reads the relative temperature (see [Temperature] for the semantic of the register)
+    pub async fn read_temperature(&mut self) -> Result<Temperature, SpiError> {
+        Ok(Temperature::from_raw(self.read_register(OUT_TEMP).await?))
+    }
+
+    /// Determines the zero-rate offset by averaging `samples` new samples per axis
+    ///
+    /// The device must be stationary while calibrating: if any axis spreads more than `threshold` counts the calibration
+    /// fails with [CalibrationError::NotStationary] and the previous bias is kept. On success the bias is applied to all following reads.
+    pub async fn calibrate(
+        &mut self,
+        samples: usize,
+        threshold: u16,
+    ) -> Result<Bias, CalibrationError> {
+        let mut window = SampleWindow::new(threshold);
+        while window.len() < samples {
+            self.wait_for_new_sample().await?;
+            let (x, y, z, _temp) = self.read_raw().await?;
+            window.add([x, y, z]);
+        }
+        let bias = window.bias()?;
+        self.bias = bias;
+        Ok(bias)
+    }
+    /// Runs [L3gd20::calibrate] and adds the resulting bias at the current temperature to `fit`
+    ///
+    /// Call this repeatedly while the temperature of the board changes (e.g. after power up or in a climate chamber)
+    /// and set the model of [TemperatureBiasFit::fit] with [L3gd20::set_temperature_model].
+    pub async fn add_temperature_point(
+        &mut self,
+        fit: &mut TemperatureBiasFit,
+        samples: usize,
+        threshold: u16,
+    ) -> Result<(), CalibrationError> {
+        let bias = self.calibrate(samples, threshold).await?;
+        let temp = self.read_temperature().await?;
+        fit.add(temp, bias);
+        Ok(())
+    }
+    /// Compensates the bias over temperature instead of using the static bias
+    pub fn set_temperature_model(&mut self, model: Option<TemperatureBiasModel>) {
+        self.temperature_model = model;
+    }
+    pub fn bias(&self) -> Bias {
+        self.bias
+    }
+    pub fn set_bias(&mut self, bias: Bias) {
+        self.bias = bias;
+    }
+    /// Refines the bias with every [L3gd20::read_values] call while the device is detected as still
+    pub fn enable_background_calibration(&mut self, background: BackgroundCalibration) {
+        self.background = Some(background);
+    }
+    pub fn disable_background_calibration(&mut self) {
+        self.background = None;
+    }
+
+    /// Runs the built-in self-test and compares the output change with the datasheet limits
+    ///
+    /// The device should be stationary. The output data rate is set to 95Hz while testing, CTRL_REG1 and CTRL_REG4
+    /// are restored afterwards, also if a step fails. The first error is returned then.
+    ///
+    /// Not cancel safe: if the future is dropped while testing (e.g. by a deadline) the self-test stays enabled and
+    /// falsifies all following samples, and the data rate stays at 95Hz. Run the self-test again to completion then,
+    /// it always ends with the self-test disabled.
+    pub async fn self_test(&mut self) -> Result<SelfTestReport, SpiError> {
+        let ctrl1 = self.read_register(CTRL_REG1).await?;
+        let ctrl4 = self.read_register(CTRL_REG4).await?;
+        let outputs = self.self_test_outputs(ctrl4).await;
+        let restore4 = self.write_register(CTRL_REG4, ctrl4 & !CTRL4_ST_MASK).await;
+        let restore1 = self.write_register(CTRL_REG1, ctrl1).await;
+        let [baseline, positive, negative] = outputs?;
+        restore4?;
+        restore1?;
+        lilos::exec::sleep_for(SELF_TEST_SETTLE).await;
 
-        let x = i16::from_be_bytes([l3gd20[9], l3gd20[8]]);
-        let y = i16::from_be_bytes([l3gd20[11], l3gd20[10]]);
-        let z = i16::from_be_bytes([l3gd20[13], l3gd20[12]]);
-        (x, y, z, l3gd20[6] as i8)
+        let sensitivity = self.full_scale.sensitivity();
+        let change = |st: [f32; 3]| [0, 1, 2].map(|i| (st[i] - baseline[i]) * sensitivity);
+        Ok(SelfTestReport {
+            positive: change(positive),
+            negative: change(negative),
+            limits: SelfTestLimits::for_full_scale(self.full_scale),
+        })
     }
+    /// Mean raw outputs without, with the positive and with the negative self-test, leaves the self-test enabled
+    async fn self_test_outputs(&mut self, ctrl4: u8) -> Result<[[f32; 3]; 3], SpiError> {
+        self.write_register(CTRL_REG1, 0b0000_1111).await?;
+        lilos::exec::sleep_for(SELF_TEST_SETTLE).await;
+        let baseline = self.average_raw(SELF_TEST_SAMPLES).await?;
 
-    fn select_device(&mut self) {
-        self.cs.set_low().unwrap_or_default();
+        self.write_register(CTRL_REG4, (ctrl4 & !CTRL4_ST_MASK) | CTRL4_ST_POSITIVE)
+            .await?;
+        lilos::exec::sleep_for(SELF_TEST_SETTLE).await;
+        let positive = self.average_raw(SELF_TEST_SAMPLES).await?;
+
+        self.write_register(CTRL_REG4, (ctrl4 & !CTRL4_ST_MASK) | CTRL4_ST_NEGATIVE)
+            .await?;
+        lilos::exec::sleep_for(SELF_TEST_SETTLE).await;
+        let negative = self.average_raw(SELF_TEST_SAMPLES).await?;
+        Ok([baseline, positive, negative])
+    }
+
+    /// Routes the given event to the DRDY/INT2 Pin
+    ///
+    /// Use [L3gd20::wait_for_data] afterwards to sleep until the event occurs.
+    pub async fn enable_data_ready_interrupt(
+        &mut self,
+        mode: DataReadyMode,
+    ) -> Result<(), SpiError> {
+        match mode {
+            DataReadyMode::DataReady => {
+                self.write_register(FIFO_CTRL_REG, FIFO_MODE_BYPASS).await?;
+                self.modify_register(CTRL_REG5, CTRL5_FIFO_EN, 0).await?;
+                self.modify_register(CTRL_REG3, CTRL3_INT2_MASK, CTRL3_I2_DRDY)
+                    .await?;
+            }
+            DataReadyMode::Watermark(level) => {
+                self.write_register(FIFO_CTRL_REG, FIFO_MODE_STREAM | (level & FIFO_WTM_MASK))
+                    .await?;
+                self.modify_register(CTRL_REG5, CTRL5_FIFO_EN, CTRL5_FIFO_EN)
+                    .await?;
+                self.modify_register(CTRL_REG3, CTRL3_INT2_MASK, CTRL3_I2_WTM)
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+    /// Disables all events on the DRDY/INT2 Pin and returns to FIFO bypass mode
+    pub async fn disable_data_ready_interrupt(&mut self) -> Result<(), SpiError> {
+        self.modify_register(CTRL_REG3, CTRL3_INT2_MASK, 0).await?;
+        self.modify_register(CTRL_REG5, CTRL5_FIFO_EN, 0).await?;
+        self.write_register(FIFO_CTRL_REG, FIFO_MODE_BYPASS).await?;
+        Ok(())
+    }
+    /// Waits until a new sample or the FIFO watermark is ready as configured by [L3gd20::enable_data_ready_interrupt]
+    ///
+    /// The task is only woken by the EXTI interrupt of the DRDY/INT2 Pin.
+    pub async fn wait_for_data<P: InputPin>(&mut self, int2: &IntLine<P>) {
+        int2.wait_for_active().await
+    }
+    /// Returns the number of unread samples in the FIFO
+    pub async fn fifo_level(&mut self) -> Result<u8, SpiError> {
+        Ok(self.read_register(FIFO_SRC_REG).await? & FIFO_SRC_FSS_MASK)
+    }
+
+    /// Configures the interrupt generator and routes it to the INT1 Pin
+    ///
+    /// Use [L3gd20::wait_for_motion] afterwards to sleep until a rotation exceeds the configured thresholds.
+    pub async fn configure_motion_interrupt(
+        &mut self,
+        config: &MotionConfig,
+    ) -> Result<(), SpiError> {
+        // disable the generator while it is reconfigured
+        self.write_register(INT1_CFG, 0).await?;
+        self.write_register(CTRL_REG2, config.ctrl_reg2()).await?;
+        if let Some(reference) = config.reference() {
+            self.write_register(REFERENCE, reference).await?;
+        }
+        self.write_registers(INT1_TSH_XH, &config.thresholds())
+            .await?;
+        self.write_register(INT1_DURATION, config.int1_duration())
+            .await?;
+        self.modify_register(
+            CTRL_REG5,
+            CTRL5_HPEN | CTRL5_INT1_SEL_MASK,
+            config.ctrl_reg5(),
+        )
+        .await?;
+        self.write_register(INT1_CFG, config.int1_cfg()).await?;
+        self.modify_register(CTRL_REG3, CTRL3_I1_INT1, CTRL3_I1_INT1)
+            .await?;
+        Ok(())
+    }
+    /// Disables the interrupt generator and the INT1 Pin
+    pub async fn disable_motion_interrupt(&mut self) -> Result<(), SpiError> {
+        self.modify_register(CTRL_REG3, CTRL3_I1_INT1, 0).await?;
+        self.write_register(INT1_CFG, 0).await?;
+        Ok(())
+    }
+    /// Waits until the interrupt generator configured by [L3gd20::configure_motion_interrupt] fires
+    ///
+    /// The task is only woken by the EXTI interrupt of the INT1 Pin. Reading the source releases a latched interrupt.
+    pub async fn wait_for_motion<P: InputPin>(
+        &mut self,
+        int1: &IntLine<P>,
+    ) -> Result<MotionSource, SpiError> {
+        loop {
+            int1.wait_for_active().await;
+            let source = MotionSource::from_raw(self.read_register(INT1_SRC).await?);
+            if source.is_active() {
+                return Ok(source);
+            }
+            // the (not latched) event was already gone when the source was read
+            lilos::exec::yield_cpu().await;
+        }
+    }
+
+    /// Polls the status register until a new set of x, y and z rates is available
+    async fn wait_for_new_sample(&mut self) -> Result<(), SpiError> {
+        while self.read_register(STATUS_REG).await? & STATUS_ZYXDA == 0 {
+            lilos::exec::sleep_for(Duration::from_millis(1)).await;
+        }
+        Ok(())
+    }
+    /// Mean of `samples` new uncompensated samples per axis
+    async fn average_raw(&mut self, samples: usize) -> Result<[f32; 3], SpiError> {
+        let mut sum = [0i32; 3];
+        for _ in 0..samples {
+            self.wait_for_new_sample().await?;
+            let (x, y, z, _temp) = self.read_raw().await?;
+            sum[0] += x as i32;
+            sum[1] += y as i32;
+            sum[2] += z as i32;
+        }
+        Ok(sum.map(|s| s as f32 / samples as f32))
+    }
+    fn rate_sample(&self, timestamp_us: u64, x: i16, y: i16, z: i16) -> RateSample {
+        let sensitivity = self.full_scale.sensitivity();
+        RateSample {
+            timestamp_us,
+            rate: [x, y, z].map(|r| r as f32 * sensitivity),
+        }
     }
-    fn deselect_device(&mut self) {
-        self.cs.set_high().unwrap_or_default();
+    /// Deadline of a register access started now, see [L3gd20::set_timeout]
+    fn deadline(&self) -> Option<TickTime> {
+        self.timeout.map(|timeout| TickTime::now() + timeout)
     }
 }
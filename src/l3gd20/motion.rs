@@ -0,0 +1,253 @@
+use super::registers::*;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Axis {
+    X,
+    Y,
+    Z,
+}
+
+impl Axis {
+    /// Position of the axis high event in INT1_CFG and INT1_SRC - the low event is one bit below
+    fn high_bit(self) -> u8 {
+        match self {
+            Axis::X => 1 << 1,
+            Axis::Y => 1 << 3,
+            Axis::Z => 1 << 5,
+        }
+    }
+    fn low_bit(self) -> u8 {
+        self.high_bit() >> 1
+    }
+    pub(crate) fn index(self) -> usize {
+        match self {
+            Axis::X => 0,
+            Axis::Y => 1,
+            Axis::Z => 2,
+        }
+    }
+}
+
+/// How the enabled axis events are combined to an interrupt
+#[derive(Clone, Copy, PartialEq)]
+pub enum Combination {
+    /// Any enabled event triggers the interrupt
+    Or,
+    /// All enabled events must be active
+    And,
+}
+
+/// Operating mode of the high-pass filter (HPM bits of CTRL_REG2)
+#[derive(Clone, Copy, PartialEq)]
+pub enum HighPassMode {
+    /// Normal mode, reset by reading the REFERENCE register
+    NormalReset = 0b00,
+    /// The REFERENCE register is subtracted from the rates
+    Reference = 0b01,
+    Normal = 0b10,
+    /// Filter is reset on every interrupt event
+    AutoReset = 0b11,
+}
+
+/// High-pass filter applied to the rates before they are compared with the thresholds
+#[derive(Clone, Copy, PartialEq)]
+pub struct HighPassFilter {
+    pub mode: HighPassMode,
+    /// HPCF cut off selection (0..=9), the cut off frequency depends on the output data rate - see datasheet Table 26
+    pub cutoff: u8,
+    /// Reference value for [HighPassMode::Reference]
+    pub reference: u8,
+}
+
+/// Configuration of the INT1 interrupt generator
+///
+/// Every axis has a single 15 bit threshold in raw counts, set with [MotionConfig::threshold] and shared by the high
+/// and the low event of the axis. A high event is active while the rate is above it, a low event while the rate is
+/// below it.
+#[derive(Clone, Copy, PartialEq)]
+pub struct MotionConfig {
+    threshold: [u16; 3],
+    events: u8,
+    duration: u8,
+    wait: bool,
+    combination: Combination,
+    latch: bool,
+    high_pass: Option<HighPassFilter>,
+}
+
+impl Default for MotionConfig {
+    fn default() -> Self {
+        Self {
+            threshold: [0; 3],
+            events: 0,
+            duration: 0,
+            wait: false,
+            combination: Combination::Or,
+            latch: false,
+            high_pass: None,
+        }
+    }
+}
+
+impl MotionConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Threshold of both events of `axis` in raw counts (max. 0x7FFF)
+    pub fn threshold(mut self, axis: Axis, threshold: u16) -> Self {
+        self.threshold[axis.index()] = threshold & 0x7FFF;
+        self
+    }
+    /// Enables the high event of `axis`
+    pub fn high(mut self, axis: Axis) -> Self {
+        self.events |= axis.high_bit();
+        self
+    }
+    /// Enables the low event of `axis`
+    pub fn low(mut self, axis: Axis) -> Self {
+        self.events |= axis.low_bit();
+        self
+    }
+    /// Minimum number of samples (max. 127) an event must be active before the interrupt is raised
+    ///
+    /// With `wait` the interrupt also falls only after the event was inactive for the same duration.
+    pub fn duration(mut self, samples: u8, wait: bool) -> Self {
+        self.duration = samples & 0x7F;
+        self.wait = wait;
+        self
+    }
+    pub fn combination(mut self, combination: Combination) -> Self {
+        self.combination = combination;
+        self
+    }
+    /// Keeps the interrupt active until INT1_SRC is read
+    pub fn latch(mut self, latch: bool) -> Self {
+        self.latch = latch;
+        self
+    }
+    pub fn high_pass(mut self, filter: Option<HighPassFilter>) -> Self {
+        self.high_pass = filter;
+        self
+    }
+
+    pub(crate) fn int1_cfg(&self) -> u8 {
+        let mut cfg = self.events;
+        if self.combination == Combination::And {
+            cfg |= INT1_CFG_AND;
+        }
+        if self.latch {
+            cfg |= INT1_CFG_LIR;
+        }
+        cfg
+    }
+    /// Content of INT1_TSH_XH..INT1_TSH_ZL
+    pub(crate) fn thresholds(&self) -> [u8; 6] {
+        let mut regs = [0; 6];
+        for (i, ths) in self.threshold.iter().enumerate() {
+            regs[2 * i..2 * i + 2].copy_from_slice(&ths.to_be_bytes());
+        }
+        regs
+    }
+    pub(crate) fn int1_duration(&self) -> u8 {
+        if self.wait {
+            self.duration | INT1_DURATION_WAIT
+        } else {
+            self.duration
+        }
+    }
+    pub(crate) fn ctrl_reg2(&self) -> u8 {
+        match self.high_pass {
+            Some(hpf) => (hpf.mode as u8) << 4 | (hpf.cutoff & 0x0F),
+            None => 0,
+        }
+    }
+    /// Bits of CTRL_REG5 within [CTRL5_HPEN] and [CTRL5_INT1_SEL_MASK]
+    pub(crate) fn ctrl_reg5(&self) -> u8 {
+        match self.high_pass {
+            Some(_) => CTRL5_HPEN | CTRL5_INT1_SEL_HPF,
+            None => 0,
+        }
+    }
+    pub(crate) fn reference(&self) -> Option<u8> {
+        self.high_pass.map(|hpf| hpf.reference)
+    }
+}
+
+/// Content of INT1_SRC: the events which caused the interrupt
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct MotionSource(u8);
+
+impl MotionSource {
+    pub fn from_raw(int1_src: u8) -> Self {
+        Self(int1_src)
+    }
+    /// One or more events are active
+    pub fn is_active(&self) -> bool {
+        self.0 & INT1_SRC_IA != 0
+    }
+    pub fn is_high(&self, axis: Axis) -> bool {
+        self.0 & axis.high_bit() != 0
+    }
+    pub fn is_low(&self, axis: Axis) -> bool {
+        self.0 & axis.low_bit() != 0
+    }
+    /// Returns if any event of `axis` triggered
+    pub fn triggered(&self, axis: Axis) -> bool {
+        self.is_high(axis) || self.is_low(axis)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn register_encoding() {
+        let cfg = MotionConfig::new()
+            .threshold(Axis::X, 0x1234)
+            .high(Axis::X)
+            .threshold(Axis::Z, 0xFFFF)
+            .low(Axis::Z)
+            .duration(0xFF, true)
+            .combination(Combination::And)
+            .latch(true);
+        assert_eq!(cfg.int1_cfg(), 0b1101_0010);
+        assert_eq!(cfg.thresholds(), [0x12, 0x34, 0, 0, 0x7F, 0xFF]);
+        assert_eq!(cfg.int1_duration(), 0xFF);
+        assert_eq!(cfg.ctrl_reg2(), 0);
+        assert_eq!(cfg.ctrl_reg5(), 0);
+        assert_eq!(cfg.reference(), None);
+    }
+
+    #[test]
+    fn high_and_low_event_share_the_threshold() {
+        let cfg = MotionConfig::new()
+            .threshold(Axis::Y, 500)
+            .high(Axis::Y)
+            .low(Axis::Y);
+        assert_eq!(cfg.int1_cfg(), 0b0000_1100);
+        assert_eq!(cfg.thresholds(), [0, 0, 0x01, 0xF4, 0, 0]);
+    }
+
+    #[test]
+    fn high_pass_encoding() {
+        let cfg = MotionConfig::new().high_pass(Some(HighPassFilter {
+            mode: HighPassMode::Reference,
+            cutoff: 9,
+            reference: 42,
+        }));
+        assert_eq!(cfg.ctrl_reg2(), 0b0001_1001);
+        assert_eq!(cfg.ctrl_reg5(), 0b0001_0100);
+        assert_eq!(cfg.reference(), Some(42));
+    }
+
+    #[test]
+    fn source_decoding() {
+        let src = MotionSource::from_raw(0b0110_0001);
+        assert!(src.is_active());
+        assert!(src.is_high(Axis::Z));
+        assert!(src.is_low(Axis::X));
+        assert!(!src.triggered(Axis::Y));
+        assert!(!MotionSource::from_raw(0).is_active());
+    }
+}
@@ -0,0 +1,251 @@
+use core::ops::Mul;
+use libm::{asinf, atan2f, cosf, sinf, sqrtf};
+
+const DEG_TO_RAD: f32 = core::f32::consts::PI / 180.0;
+const RAD_TO_DEG: f32 = 180.0 / core::f32::consts::PI;
+
+/// Calibrated angular rates in °/s of one sample and the time it was taken
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RateSample {
+    /// Time stamp of the sample in µs. Only the difference between two samples is used.
+    pub timestamp_us: u64,
+    /// Rates around the x, y and z-Axis in °/s
+    pub rate: [f32; 3],
+}
+
+/// Roll (x), pitch (y) and yaw (z) angles in ° (z-y-x convention)
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct EulerAngles {
+    pub roll: f32,
+    pub pitch: f32,
+    /// Wrapped into -180°..180°
+    pub yaw: f32,
+}
+
+/// Unit quaternion describing the rotation from the start (or zero) orientation into the current one
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Quaternion {
+    pub w: f32,
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+}
+
+impl Quaternion {
+    pub const IDENTITY: Quaternion = Quaternion {
+        w: 1.0,
+        x: 0.0,
+        y: 0.0,
+        z: 0.0,
+    };
+
+    /// Rotation of `angle` rad around the z-Axis
+    fn from_yaw(angle: f32) -> Self {
+        Self {
+            w: cosf(angle / 2.0),
+            x: 0.0,
+            y: 0.0,
+            z: sinf(angle / 2.0),
+        }
+    }
+    pub fn normalized(&self) -> Quaternion {
+        let norm = sqrtf(self.w * self.w + self.x * self.x + self.y * self.y + self.z * self.z);
+        Quaternion {
+            w: self.w / norm,
+            x: self.x / norm,
+            y: self.y / norm,
+            z: self.z / norm,
+        }
+    }
+    pub fn to_euler(self) -> EulerAngles {
+        let (w, x, y, z) = (self.w, self.x, self.y, self.z);
+        let roll = atan2f(2.0 * (w * x + y * z), 1.0 - 2.0 * (x * x + y * y));
+        // clamp numerical noise at +-90° pitch
+        let pitch = asinf((2.0 * (w * y - z * x)).clamp(-1.0, 1.0));
+        let yaw = atan2f(2.0 * (w * z + x * y), 1.0 - 2.0 * (y * y + z * z));
+        EulerAngles {
+            roll: roll * RAD_TO_DEG,
+            pitch: pitch * RAD_TO_DEG,
+            yaw: wrap_degrees(yaw * RAD_TO_DEG),
+        }
+    }
+}
+
+/// Hamilton product
+impl Mul for Quaternion {
+    type Output = Quaternion;
+
+    fn mul(self, rhs: Quaternion) -> Quaternion {
+        Quaternion {
+            w: self.w * rhs.w - self.x * rhs.x - self.y * rhs.y - self.z * rhs.z,
+            x: self.w * rhs.x + self.x * rhs.w + self.y * rhs.z - self.z * rhs.y,
+            y: self.w * rhs.y - self.x * rhs.z + self.y * rhs.w + self.z * rhs.x,
+            z: self.w * rhs.z + self.x * rhs.y - self.y * rhs.x + self.z * rhs.w,
+        }
+    }
+}
+
+/// Wraps an angle in ° into -180°..180°
+pub fn wrap_degrees(angle: f32) -> f32 {
+    let wrapped = (angle + 180.0) % 360.0;
+    if wrapped < 0.0 {
+        wrapped + 180.0
+    } else {
+        wrapped - 180.0
+    }
+}
+
+/// Integrates angular rates over time into an orientation
+///
+/// The body rates of every [RateSample] are applied to a quaternion with the time passed since the previous sample.
+/// The first sample after creation or [Orientation::reset] only sets the time base.
+pub struct Orientation {
+    q: Quaternion,
+    last_timestamp_us: Option<u64>,
+    rate: [f32; 3],
+}
+
+impl Default for Orientation {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Orientation {
+    pub fn new() -> Self {
+        Self {
+            q: Quaternion::IDENTITY,
+            last_timestamp_us: None,
+            rate: [0.0; 3],
+        }
+    }
+    /// Sets the current orientation as new zero and restarts the time base
+    pub fn reset(&mut self) {
+        self.q = Quaternion::IDENTITY;
+        self.last_timestamp_us = None;
+    }
+    /// Sets the current heading as zero yaw while keeping roll and pitch
+    pub fn zero_yaw(&mut self) {
+        let yaw = self.angles().yaw * DEG_TO_RAD;
+        self.q = (Quaternion::from_yaw(-yaw) * self.q).normalized();
+    }
+    pub fn update(&mut self, sample: &RateSample) {
+        self.rate = sample.rate;
+        let last = self.last_timestamp_us.replace(sample.timestamp_us);
+        let Some(last) = last else {
+            return;
+        };
+        let dt = sample.timestamp_us.saturating_sub(last) as f32 / 1_000_000.0;
+        let [wx, wy, wz] = sample.rate.map(|r| r * DEG_TO_RAD);
+        let rate = sqrtf(wx * wx + wy * wy + wz * wz);
+        let angle = rate * dt;
+        if angle <= f32::EPSILON {
+            return;
+        }
+        let s = sinf(angle / 2.0) / rate;
+        let dq = Quaternion {
+            w: cosf(angle / 2.0),
+            x: wx * s,
+            y: wy * s,
+            z: wz * s,
+        };
+        self.q = (self.q * dq).normalized();
+    }
+    pub fn quaternion(&self) -> Quaternion {
+        self.q
+    }
+    pub fn angles(&self) -> EulerAngles {
+        self.q.to_euler()
+    }
+    /// Angular rates in °/s of the last [Orientation::update], so other tasks need not read the gyro themselves
+    pub fn rate(&self) -> [f32; 3] {
+        self.rate
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(value: f32, expected: f32) {
+        assert!(
+            (value - expected).abs() < 0.05,
+            "{} is not close to {}",
+            value,
+            expected
+        );
+    }
+
+    /// Feeds `rate` for `duration_ms` with a sample every 10ms
+    fn rotate(orientation: &mut Orientation, start_ms: u64, duration_ms: u64, rate: [f32; 3]) {
+        for t in (start_ms..=start_ms + duration_ms).step_by(10) {
+            orientation.update(&RateSample {
+                timestamp_us: t * 1000,
+                rate,
+            });
+        }
+    }
+
+    #[test]
+    fn first_sample_only_sets_time_base() {
+        let mut orientation = Orientation::new();
+        orientation.update(&RateSample {
+            timestamp_us: 1_000_000,
+            rate: [100.0, 100.0, 100.0],
+        });
+        assert_eq!(orientation.quaternion(), Quaternion::IDENTITY);
+        assert_eq!(orientation.rate(), [100.0, 100.0, 100.0]);
+    }
+
+    #[test]
+    fn single_axis_rotations() {
+        let mut orientation = Orientation::new();
+        rotate(&mut orientation, 0, 1000, [30.0, 0.0, 0.0]);
+        assert_close(orientation.angles().roll, 30.0);
+
+        orientation.reset();
+        rotate(&mut orientation, 2000, 500, [0.0, -40.0, 0.0]);
+        assert_close(orientation.angles().pitch, -20.0);
+
+        orientation.reset();
+        rotate(&mut orientation, 3000, 2000, [0.0, 0.0, 45.0]);
+        let angles = orientation.angles();
+        assert_close(angles.roll, 0.0);
+        assert_close(angles.pitch, 0.0);
+        assert_close(angles.yaw, 90.0);
+    }
+
+    #[test]
+    fn uses_real_time_stamps() {
+        let mut orientation = Orientation::new();
+        for t in [0u64, 7, 30, 31, 100] {
+            orientation.update(&RateSample {
+                timestamp_us: t * 1000,
+                rate: [0.0, 0.0, 100.0],
+            });
+        }
+        assert_close(orientation.angles().yaw, 10.0);
+    }
+
+    #[test]
+    fn yaw_wraps_around() {
+        let mut orientation = Orientation::new();
+        rotate(&mut orientation, 0, 3000, [0.0, 0.0, 90.0]);
+        assert_close(orientation.angles().yaw, -90.0);
+        assert_close(wrap_degrees(190.0), -170.0);
+        assert_close(wrap_degrees(-190.0), 170.0);
+        assert_close(wrap_degrees(720.0), 0.0);
+    }
+
+    #[test]
+    fn zero_yaw_keeps_roll() {
+        let mut orientation = Orientation::new();
+        rotate(&mut orientation, 0, 1000, [0.0, 0.0, 50.0]);
+        rotate(&mut orientation, 1000, 1000, [20.0, 0.0, 0.0]);
+        orientation.zero_yaw();
+        let angles = orientation.angles();
+        assert_close(angles.yaw, 0.0);
+        assert_close(angles.pitch, 0.0);
+        assert_close(angles.roll, 20.0);
+    }
+}
@@ -0,0 +1,73 @@
+pub const WHO_AM_I: u8 = 0x0F;
+pub const CTRL_REG1: u8 = 0x20;
+pub const CTRL_REG2: u8 = 0x21;
+pub const CTRL_REG3: u8 = 0x22;
+pub const CTRL_REG4: u8 = 0x23;
+pub const CTRL_REG5: u8 = 0x24;
+pub const REFERENCE: u8 = 0x25;
+pub const OUT_TEMP: u8 = 0x26;
+pub const STATUS_REG: u8 = 0x27;
+pub const OUT_X_L: u8 = 0x28;
+pub const FIFO_CTRL_REG: u8 = 0x2E;
+pub const FIFO_SRC_REG: u8 = 0x2F;
+pub const INT1_CFG: u8 = 0x30;
+pub const INT1_SRC: u8 = 0x31;
+pub const INT1_TSH_XH: u8 = 0x32;
+pub const INT1_DURATION: u8 = 0x38;
+
+/// Expected content of [WHO_AM_I]
+pub const DEVICE_ID: u8 = 0xD4;
+
+// CTRL_REG3
+/// Interrupt generator on INT1
+pub const CTRL3_I1_INT1: u8 = 1 << 7;
+/// Data Ready on DRDY/INT2
+pub const CTRL3_I2_DRDY: u8 = 1 << 3;
+/// FIFO Watermark interrupt on DRDY/INT2
+pub const CTRL3_I2_WTM: u8 = 1 << 2;
+/// All events routed to DRDY/INT2
+pub const CTRL3_INT2_MASK: u8 = 0b1111;
+
+// CTRL_REG4
+/// Full scale selection
+pub const CTRL4_FS_MASK: u8 = 0b11 << 4;
+pub const CTRL4_ST_MASK: u8 = 0b11 << 1;
+/// Self-test 0 (+)
+pub const CTRL4_ST_POSITIVE: u8 = 0b01 << 1;
+/// Self-test 1 (-)
+pub const CTRL4_ST_NEGATIVE: u8 = 0b11 << 1;
+
+// CTRL_REG5
+pub const CTRL5_FIFO_EN: u8 = 1 << 6;
+/// High-pass filter enable
+pub const CTRL5_HPEN: u8 = 1 << 4;
+/// INT1 selection: data after the high-pass filter
+pub const CTRL5_INT1_SEL_HPF: u8 = 0b01 << 2;
+pub const CTRL5_INT1_SEL_MASK: u8 = 0b11 << 2;
+
+// STATUS_REG
+/// X, Y, Z-axis new data available
+pub const STATUS_ZYXDA: u8 = 1 << 3;
+
+// FIFO_CTRL_REG
+pub const FIFO_MODE_BYPASS: u8 = 0b000 << 5;
+pub const FIFO_MODE_STREAM: u8 = 0b010 << 5;
+/// Watermark level is 5 bit wide
+pub const FIFO_WTM_MASK: u8 = 0b1_1111;
+
+// FIFO_SRC_REG
+pub const FIFO_SRC_WTM: u8 = 1 << 7;
+/// Number of unread samples stored in the FIFO
+pub const FIFO_SRC_FSS_MASK: u8 = 0b1_1111;
+
+// INT1_CFG
+pub const INT1_CFG_AND: u8 = 1 << 7;
+/// Latch interrupt request until INT1_SRC is read
+pub const INT1_CFG_LIR: u8 = 1 << 6;
+
+// INT1_SRC
+/// One or more interrupts have been generated
+pub const INT1_SRC_IA: u8 = 1 << 6;
+
+// INT1_DURATION
+pub const INT1_DURATION_WAIT: u8 = 1 << 7;
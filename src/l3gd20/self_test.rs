@@ -0,0 +1,89 @@
+use super::motion::Axis;
+use super::FullScale;
+
+/// Accepted magnitude of the output change caused by the self-test in °/s
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SelfTestLimits {
+    pub min: f32,
+    pub max: f32,
+}
+
+impl SelfTestLimits {
+    /// The datasheet only specifies the typical output change (130, 200 and 530 °/s).
+    /// We accept 50% to 150% of it.
+    pub fn for_full_scale(full_scale: FullScale) -> Self {
+        let typical = match full_scale {
+            FullScale::Dps250 => 130.0,
+            FullScale::Dps500 => 200.0,
+            FullScale::Dps2000 => 530.0,
+        };
+        Self {
+            min: typical * 0.5,
+            max: typical * 1.5,
+        }
+    }
+    fn contains(&self, change: f32) -> bool {
+        let change = if change < 0.0 { -change } else { change };
+        change >= self.min && change <= self.max
+    }
+}
+
+/// Result of [super::L3gd20::self_test]
+///
+/// Holds the output change of every axis in °/s for the positive (ST = 01) and negative (ST = 11) self-test
+/// compared to the output without self-test.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SelfTestReport {
+    pub positive: [f32; 3],
+    pub negative: [f32; 3],
+    pub limits: SelfTestLimits,
+}
+
+impl SelfTestReport {
+    /// Both changes of the axis are within the limits and point in opposite directions
+    pub fn axis_passed(&self, axis: Axis) -> bool {
+        let pos = self.positive[axis.index()];
+        let neg = self.negative[axis.index()];
+        self.limits.contains(pos) && self.limits.contains(neg) && (pos * neg) < 0.0
+    }
+    pub fn passed(&self) -> bool {
+        [Axis::X, Axis::Y, Axis::Z]
+            .iter()
+            .all(|axis| self.axis_passed(*axis))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(positive: [f32; 3], negative: [f32; 3]) -> SelfTestReport {
+        SelfTestReport {
+            positive,
+            negative,
+            limits: SelfTestLimits::for_full_scale(FullScale::Dps250),
+        }
+    }
+
+    #[test]
+    fn passes_within_limits() {
+        let report = report([130.0, -100.0, 150.0], [-125.0, 90.0, -190.0]);
+        assert!(report.passed());
+    }
+
+    #[test]
+    fn fails_on_dead_or_saturated_axis() {
+        let report = report([130.0, 2.0, 300.0], [-130.0, -1.0, -300.0]);
+        assert!(report.axis_passed(Axis::X));
+        assert!(!report.axis_passed(Axis::Y));
+        assert!(!report.axis_passed(Axis::Z));
+        assert!(!report.passed());
+    }
+
+    #[test]
+    fn fails_without_sign_change() {
+        let report = report([130.0, 130.0, 130.0], [130.0, -130.0, -130.0]);
+        assert!(!report.axis_passed(Axis::X));
+        assert!(!report.passed());
+    }
+}
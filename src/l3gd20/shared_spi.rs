@@ -0,0 +1,159 @@
+use super::async_spi::{AsyncSpiBus, AsyncSpiDevice, ChipSelectGuard, SpiError};
+use super::dma_spi::DmaSpi;
+use crate::deadline::before;
+use crate::mutex::{Mutex, MutexGuard};
+use core::future::Future;
+use embedded_hal::digital::v2::OutputPin;
+use embedded_hal::spi::{Mode, Phase, Polarity};
+use lilos::time::TickTime;
+use stm32f3xx_hal::pac::SPI1;
+use stm32f3xx_hal::rcc::Clocks;
+use stm32f3xx_hal::spi::Spi;
+use stm32f3xx_hal::time::rate::Hertz;
+
+/// SPI_CR1: CPHA, CPOL and BR[2:0]
+const CR1_CPHA: u32 = 1 << 0;
+const CR1_CPOL: u32 = 1 << 1;
+const CR1_BR_SHIFT: u32 = 3;
+const CR1_BR_MASK: u32 = 0b111 << CR1_BR_SHIFT;
+const CR1_SPE: u32 = 1 << 6;
+
+/// Bus settings of a single device, applied before each of its transactions
+#[derive(Clone, Copy, PartialEq)]
+pub struct SpiDeviceConfig {
+    pub mode: Mode,
+    /// Upper limit of the SCK frequency. The next lower frequency the prescaler allows is used.
+    pub frequency: Hertz,
+}
+
+/// Buses whose mode and frequency can be changed between transactions
+pub trait SpiConfigure {
+    /// `pclk` is the clock of the APB the peripheral is connected to
+    fn configure(&mut self, config: &SpiDeviceConfig, pclk: Hertz);
+}
+
+impl<Pins> SpiConfigure for Spi<SPI1, Pins, u8> {
+    fn configure(&mut self, config: &SpiDeviceConfig, pclk: Hertz) {
+        let spi = unsafe { &*SPI1::ptr() };
+        let mut cr1 = spi.cr1.read().bits() & !(CR1_CPHA | CR1_CPOL | CR1_BR_MASK);
+        if config.mode.polarity == Polarity::IdleHigh {
+            cr1 |= CR1_CPOL;
+        }
+        if config.mode.phase == Phase::CaptureOnSecondTransition {
+            cr1 |= CR1_CPHA;
+        }
+        cr1 |= (baud_rate_prescaler(pclk.0, config.frequency.0) as u32) << CR1_BR_SHIFT;
+        // CPOL, CPHA and BR must only be changed while the peripheral is disabled
+        spi.cr1.write(|w| unsafe { w.bits(cr1 & !CR1_SPE) });
+        spi.cr1.write(|w| unsafe { w.bits(cr1 | CR1_SPE) });
+    }
+}
+
+impl<Pins> SpiConfigure for DmaSpi<Pins> {
+    fn configure(&mut self, config: &SpiDeviceConfig, pclk: Hertz) {
+        self.spi_mut().configure(config, pclk);
+    }
+}
+
+/// BR value for the highest SCK frequency (pclk / 2^(BR+1)) not above `frequency`
+fn baud_rate_prescaler(pclk: u32, frequency: u32) -> u8 {
+    let mut br = 0;
+    while br < 7 && pclk >> (br + 1) > frequency {
+        br += 1;
+    }
+    br
+}
+
+/// SPI bus shared by several devices
+///
+/// Every device gets its own handle with chip-select and [SpiDeviceConfig]. A transaction locks the bus, applies the
+/// device config and asserts CS only for its duration, so the devices may be used from different tasks.
+pub struct SharedSpiBus<B> {
+    bus: Mutex<B>,
+    pclk: Hertz,
+}
+
+impl<B: AsyncSpiBus + SpiConfigure> SharedSpiBus<B> {
+    /// SPI1 is clocked by PCLK2
+    pub fn new(bus: B, clocks: &Clocks) -> Self {
+        Self {
+            bus: Mutex::new(bus),
+            pclk: clocks.pclk2(),
+        }
+    }
+    /// Handle of the device selected by `cs`. CS is released immediately.
+    pub fn device<E: OutputPin>(
+        &self,
+        mut cs: E,
+        config: SpiDeviceConfig,
+    ) -> SharedSpiDevice<'_, B, E> {
+        cs.set_high().unwrap_or_default();
+        SharedSpiDevice {
+            bus: self,
+            cs,
+            config,
+        }
+    }
+    pub fn release(self) -> B {
+        self.bus.into_inner()
+    }
+}
+
+pub struct SharedSpiDevice<'a, B, E> {
+    bus: &'a SharedSpiBus<B>,
+    cs: E,
+    config: SpiDeviceConfig,
+}
+
+impl<'a, B, E> SharedSpiDevice<'a, B, E> {
+    pub fn release(self) -> E {
+        self.cs
+    }
+}
+
+impl<'a, B: AsyncSpiBus + SpiConfigure, E: OutputPin> SharedSpiDevice<'a, B, E> {
+    /// Locks the bus and applies the device config, CS is not asserted yet
+    ///
+    /// The deadline also limits the wait for the other devices.
+    pub(super) async fn lock(
+        &mut self,
+        deadline: Option<TickTime>,
+    ) -> Result<(MutexGuard<'a, B>, &mut E), SpiError> {
+        let shared: &'a SharedSpiBus<B> = self.bus;
+        let mut bus = before(deadline, shared.bus.lock())
+            .await
+            .ok_or(SpiError::Timeout)?;
+        bus.configure(&self.config, shared.pclk);
+        Ok((bus, &mut self.cs))
+    }
+}
+
+impl<'a, B: AsyncSpiBus + SpiConfigure, E: OutputPin> AsyncSpiDevice for SharedSpiDevice<'a, B, E> {
+    fn transaction<'b>(
+        &'b mut self,
+        transfer_buffer: &'b mut [u8],
+        deadline: Option<TickTime>,
+    ) -> impl Future<Output = Result<&'b [u8], SpiError>> + 'b {
+        async move {
+            let (mut bus, cs) = self.lock(deadline).await?;
+            // dropped before the bus lock, so CS is released before the next device may be selected
+            let mut guard = ChipSelectGuard::new(&mut *bus, cs);
+            let res = guard.bus().async_transfer(transfer_buffer, deadline).await;
+            guard.finish();
+            res
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prescaler_does_not_exceed_frequency() {
+        assert_eq!(baud_rate_prescaler(48_000_000, 24_000_000), 0);
+        assert_eq!(baud_rate_prescaler(48_000_000, 10_000_000), 2);
+        assert_eq!(baud_rate_prescaler(48_000_000, 6_000_000), 2);
+        assert_eq!(baud_rate_prescaler(48_000_000, 1_000), 7);
+    }
+}
@@ -0,0 +1,231 @@
+use super::calibration::Bias;
+
+/// Content of the OUT_TEMP Register
+///
+/// The L3GD20 only measures temperature changes: the register is a 8 bit two's complement value with a negative slope
+/// of -1 LSB/°C and a zero point which is not trimmed in production. Use a [TemperatureReference] to get absolute values.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Temperature {
+    raw: i8,
+}
+
+impl Temperature {
+    pub fn from_raw(raw: u8) -> Self {
+        Self { raw: raw as i8 }
+    }
+    pub fn raw(&self) -> i8 {
+        self.raw
+    }
+    /// Temperature in °C relative to the unknown zero point of the sensor - rises with rising temperature
+    pub fn relative(&self) -> i16 {
+        -(self.raw as i16)
+    }
+    /// Absolute temperature in °C based on a one point reference
+    pub fn celsius(&self, reference: &TemperatureReference) -> f32 {
+        reference.celsius + (self.relative() - reference.relative) as f32
+    }
+}
+
+/// One point reference to convert the relative [Temperature] into °C
+///
+/// Take a reading while the board temperature is known (e.g. from a reference thermometer or at room temperature after power up).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TemperatureReference {
+    relative: i16,
+    celsius: f32,
+}
+
+impl TemperatureReference {
+    pub fn new(reading: Temperature, celsius: f32) -> Self {
+        Self {
+            relative: reading.relative(),
+            celsius,
+        }
+    }
+}
+
+/// Linear model of the zero-rate offset over temperature
+///
+/// `bias(t) = offset + slope * (t - t_ref)` for every axis with `t` as relative temperature
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TemperatureBiasModel {
+    t_ref: f32,
+    offset: [f32; 3],
+    slope: [f32; 3],
+}
+
+impl TemperatureBiasModel {
+    /// Model from stored parameters, e.g. of a [TemperatureBiasFit] run at production
+    ///
+    /// `t_ref` is a relative temperature (see [Temperature::relative]), `offset` the bias at `t_ref` in counts.
+    pub fn new(t_ref: f32, offset: [f32; 3], slope: [f32; 3]) -> Self {
+        Self {
+            t_ref,
+            offset,
+            slope,
+        }
+    }
+    /// Zero-rate offset at the given temperature
+    pub fn bias_at(&self, temp: Temperature) -> Bias {
+        let dt = temp.relative() as f32 - self.t_ref;
+        let axis = |i: usize| round(self.offset[i] + self.slope[i] * dt);
+        Bias {
+            x: axis(0),
+            y: axis(1),
+            z: axis(2),
+        }
+    }
+    /// Relative temperature the offset belongs to
+    pub fn t_ref(&self) -> f32 {
+        self.t_ref
+    }
+    /// Zero-rate offset in counts at [Self::t_ref]
+    pub fn offset(&self) -> [f32; 3] {
+        self.offset
+    }
+    /// Change of the offset in counts per °C
+    pub fn slope(&self) -> [f32; 3] {
+        self.slope
+    }
+}
+
+/// Collects bias measurements at different temperatures during a calibration run and fits a [TemperatureBiasModel]
+#[derive(Default)]
+pub struct TemperatureBiasFit {
+    cnt: u32,
+    sum_t: f32,
+    sum_tt: f32,
+    sum_b: [f32; 3],
+    sum_tb: [f32; 3],
+    t_min: i16,
+    t_max: i16,
+}
+
+impl TemperatureBiasFit {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn add(&mut self, temp: Temperature, bias: Bias) {
+        let t = temp.relative();
+        if self.cnt == 0 {
+            self.t_min = t;
+            self.t_max = t;
+        }
+        self.t_min = self.t_min.min(t);
+        self.t_max = self.t_max.max(t);
+
+        let t = t as f32;
+        self.cnt += 1;
+        self.sum_t += t;
+        self.sum_tt += t * t;
+        for (i, b) in [bias.x, bias.y, bias.z].iter().enumerate() {
+            self.sum_b[i] += *b as f32;
+            self.sum_tb[i] += t * *b as f32;
+        }
+    }
+    pub fn len(&self) -> u32 {
+        self.cnt
+    }
+    pub fn is_empty(&self) -> bool {
+        self.cnt == 0
+    }
+    /// Least squares fit of all points
+    ///
+    /// Returns None until points of at least two different temperatures have been added
+    pub fn fit(&self) -> Option<TemperatureBiasModel> {
+        if self.is_empty() || self.t_min == self.t_max {
+            return None;
+        }
+        let n = self.cnt as f32;
+        let t_ref = self.sum_t / n;
+        let var = self.sum_tt / n - t_ref * t_ref;
+        let mut offset = [0.0; 3];
+        let mut slope = [0.0; 3];
+        for i in 0..3 {
+            let mean_b = self.sum_b[i] / n;
+            let cov = self.sum_tb[i] / n - t_ref * mean_b;
+            slope[i] = cov / var;
+            offset[i] = mean_b;
+        }
+        Some(TemperatureBiasModel::new(t_ref, offset, slope))
+    }
+}
+
+fn round(value: f32) -> i16 {
+    if value >= 0.0 {
+        (value + 0.5) as i16
+    } else {
+        (value - 0.5) as i16
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn relative_temperature_has_negative_slope() {
+        assert_eq!(Temperature::from_raw(0).relative(), 0);
+        assert_eq!(Temperature::from_raw(5).relative(), -5);
+        assert_eq!(Temperature::from_raw(0xFB).relative(), 5);
+    }
+
+    #[test]
+    fn absolute_temperature_from_reference() {
+        let reference = TemperatureReference::new(Temperature::from_raw(10), 25.0);
+        assert_eq!(Temperature::from_raw(10).celsius(&reference), 25.0);
+        assert_eq!(Temperature::from_raw(7).celsius(&reference), 28.0);
+        assert_eq!(Temperature::from_raw(12).celsius(&reference), 23.0);
+    }
+
+    #[test]
+    fn fit_needs_two_temperatures() {
+        let mut fit = TemperatureBiasFit::new();
+        assert!(fit.fit().is_none());
+        fit.add(Temperature::from_raw(0), Bias { x: 1, y: 2, z: 3 });
+        fit.add(Temperature::from_raw(0), Bias { x: 1, y: 2, z: 3 });
+        assert!(fit.fit().is_none());
+    }
+
+    #[test]
+    fn fit_linear_bias() {
+        let mut fit = TemperatureBiasFit::new();
+        // x rises 2 counts per °C, y falls 1 count per °C, z is constant
+        for t in [-10i16, 0, 10, 20] {
+            let raw = (-t) as i8 as u8;
+            fit.add(
+                Temperature::from_raw(raw),
+                Bias {
+                    x: 20 + 2 * t,
+                    y: -5 - t,
+                    z: 7,
+                },
+            );
+        }
+        let model = fit.fit().unwrap();
+        assert_eq!(model.slope(), [2.0, -1.0, 0.0]);
+        assert_eq!(
+            model.bias_at(Temperature::from_raw((-30i8) as u8)),
+            Bias {
+                x: 80,
+                y: -35,
+                z: 7
+            }
+        );
+    }
+
+    #[test]
+    fn model_round_trip() {
+        let mut fit = TemperatureBiasFit::new();
+        fit.add(Temperature::from_raw(0), Bias { x: 10, y: 0, z: -4 });
+        fit.add(
+            Temperature::from_raw((-10i8) as u8),
+            Bias { x: 30, y: 5, z: -4 },
+        );
+        let model = fit.fit().unwrap();
+        assert_eq!(model.t_ref(), 5.0);
+        assert_eq!(model.offset(), [20.0, 2.5, -4.0]);
+        let stored = TemperatureBiasModel::new(model.t_ref(), model.offset(), model.slope());
+        assert_eq!(stored, model);
+    }
+}
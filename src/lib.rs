@@ -1,12 +1,22 @@
 #![no_std]
 /// Basic (async) Button logic
 pub mod button;
+/// Microsecond time stamps from the DWT cycle counter
+pub mod cycle_clock;
+/// Optional deadlines of the async bus drivers
+pub mod deadline;
+/// DMA channel setup shared by the SPI and I2C drivers
+pub mod dma;
 /// Minimal access of the L3GD20 Accelerometer via (async) SPI
 pub mod l3gd20;
 /// Basic Led logic
 pub mod led;
 /// Minimal access of the LSM303DLHC Magneto/Accelerometer via (async) I2C
 pub mod lsm303dlhc;
+/// Async mutex for peripherals shared between tasks
+pub mod mutex;
+/// Register map of the orientation and sensor data served to a host by [lsm303dlhc::i2c_target::I2cTarget]
+pub mod sensor_registers;
 /// Board Defs
 pub mod stm32f3_disco_def;
 
@@ -15,9 +25,8 @@ use core::time::Duration;
 use defmt::println;
 use led::Led;
 use lilos::exec::{wake_tasks_by_mask, yield_cpu};
-use lsm303dlhc::{Lsm303Error, Lsm303dlhc};
-use stm32f3_disco_def::{EastLed, GyroScl, GyroSda, UserButton};
-use stm32f3xx_hal::pac::I2C1;
+use lsm303dlhc::{AsyncI2cDevice, Lsm303Error, Lsm303dlhc};
+use stm32f3_disco_def::{EastLed, UserButton};
 
 /// Function performing a async debouncing of a button
 ///
@@ -47,9 +56,9 @@ pub async fn debounced_press(but: &stm32f3_disco_def::UserButton) -> bool {
 /// Basic async task implementing logic to provide magnetometer data on button press
 ///
 /// For every valid button press we read x,y and z-Axis information and print it
-pub async fn task_magnetometer(
+pub async fn task_magnetometer<D: AsyncI2cDevice>(
     mut but: UserButton,
-    mut magnetometer: Lsm303dlhc<I2C1, GyroScl, GyroSda>,
+    mut magnetometer: Lsm303dlhc<D>,
 ) -> Infallible {
     loop {
         match magnetometer_statemachine(&mut but, &mut magnetometer).await {
@@ -66,9 +75,9 @@ pub async fn task_magnetometer(
 }
 
 /// Internal Fallible logic
-async fn magnetometer_statemachine(
+async fn magnetometer_statemachine<D: AsyncI2cDevice>(
     but: &mut UserButton,
-    magnetometer: &mut Lsm303dlhc<I2C1, GyroScl, GyroSda>,
+    magnetometer: &mut Lsm303dlhc<D>,
 ) -> Result<(), Lsm303Error> {
     // Write basic setup to registers
     magnetometer.setup().await?;
@@ -0,0 +1,387 @@
+use super::i2c_irq::{listen, CR1_IRQ_MASK, I2C1_NOTIFY};
+use super::i2c_no_irq::{
+    bus_error, check_idle, finish, pec_len, reload, start_transfer, wake_events, MAX_NBYTES,
+};
+use super::i2c_regs::{ISR_STOPF, ISR_TC, ISR_TCR};
+use super::recovery::{recover_bus, BusLines, I2cBusPins};
+use super::{AsyncI2cBus, I2cAddress, I2cError};
+use crate::deadline::before;
+use crate::dma::{configure, CCR_IRQ_MASK, MAX_TRANSFER};
+use core::future::Future;
+use core::task::Poll;
+use cortex_m::peripheral::NVIC;
+use lilos::time::TickTime;
+use stm32f3xx_hal::dma::{dma1, Channel, Direction, Event};
+use stm32f3xx_hal::i2c::{I2c, SclPin, SdaPin};
+use stm32f3xx_hal::pac::{interrupt, Interrupt, DMA1, I2C1};
+
+/// I2C_CR1: TXDMAEN and RXDMAEN
+const CR1_TXDMAEN: u32 = 1 << 14;
+const CR1_RXDMAEN: u32 = 1 << 15;
+
+/// I2C1 with the data moved by DMA1
+///
+/// Channel 6 sends (I2C1_TX) and channel 7 receives (I2C1_RX). The task sleeps until the end of the transfer (STOP or
+/// TC), a NACK or an error interrupt and is not woken per byte. Transfers over 255 bytes are continued on TCR, buffers
+/// over [MAX_TRANSFER] bytes are rejected with [I2cError::TooLong]. Errors are reported as the
+/// [stm32f3xx_hal::i2c::Error] variants of [I2cError], a DMA transfer error as [I2cError::Bus].
+///
+/// There is no [embedded_hal_async::i2c::I2c] implementation: its `transaction` merges adjacent operations of one
+/// direction into one transfer, while one DMA transfer covers exactly one buffer. Run ecosystem drivers on
+/// [super::i2c_irq::I2cIrq] or [super::i2c_no_irq::I2cNoIrq] instead.
+pub struct DmaI2c<SCL, SDA> {
+    i2c: I2c<I2C1, (SCL, SDA)>,
+    tx: dma1::C6,
+    rx: dma1::C7,
+    lines: Option<BusLines>,
+}
+
+impl<SCL, SDA> DmaI2c<SCL, SDA> {
+    /// Unmasks the I2C1 and DMA1 channel 6/7 interrupts in the NVIC
+    pub fn new(i2c: I2c<I2C1, (SCL, SDA)>, tx: dma1::C6, rx: dma1::C7) -> Self
+    where
+        SCL: SclPin<I2C1>,
+        SDA: SdaPin<I2C1>,
+    {
+        unsafe {
+            NVIC::unmask(Interrupt::I2C1_EV_EXTI23);
+            NVIC::unmask(Interrupt::I2C1_ER);
+            NVIC::unmask(Interrupt::DMA1_CH6);
+            NVIC::unmask(Interrupt::DMA1_CH7);
+        }
+        Self {
+            i2c,
+            tx,
+            rx,
+            lines: None,
+        }
+    }
+    pub fn free(self) -> (I2c<I2C1, (SCL, SDA)>, dma1::C6, dma1::C7) {
+        (self.i2c, self.tx, self.rx)
+    }
+    /// See [super::i2c_no_irq::I2cNoIrq::with_bus_pins]
+    pub fn with_bus_pins(mut self) -> Self
+    where
+        (SCL, SDA): I2cBusPins,
+    {
+        self.lines = Some(BusLines::of::<(SCL, SDA)>());
+        self
+    }
+
+    /// Sets up the channel of the direction for `len` bytes at `mem` and enables the DMA requests
+    fn start_dma(&mut self, read: bool, mem: u32, len: usize) {
+        let port = unsafe { &*I2C1::ptr() };
+        if read {
+            let rxdr = &port.rxdr as *const _ as u32;
+            configure(&mut self.rx, rxdr, mem, len, Direction::FromPeripheral);
+            self.rx.enable();
+            port.cr1
+                .modify(|r, w| unsafe { w.bits(r.bits() | CR1_RXDMAEN) });
+        } else {
+            let txdr = &port.txdr as *const _ as u32;
+            configure(&mut self.tx, txdr, mem, len, Direction::FromMemory);
+            self.tx.enable();
+            port.cr1
+                .modify(|r, w| unsafe { w.bits(r.bits() | CR1_TXDMAEN) });
+        }
+    }
+    /// Enables the transfer complete and error interrupts of the channel of the direction
+    fn listen_dma(&mut self, read: bool) {
+        if read {
+            self.rx.enable_interrupt(Event::TransferComplete);
+            self.rx.enable_interrupt(Event::TransferError);
+        } else {
+            self.tx.enable_interrupt(Event::TransferComplete);
+            self.tx.enable_interrupt(Event::TransferError);
+        }
+    }
+    fn stop_dma(&mut self) {
+        let port = unsafe { &*I2C1::ptr() };
+        port.cr1
+            .modify(|r, w| unsafe { w.bits(r.bits() & !(CR1_TXDMAEN | CR1_RXDMAEN)) });
+        self.tx.disable();
+        self.rx.disable();
+        self.tx.clear_event(Event::Any);
+        self.rx.clear_event(Event::Any);
+    }
+}
+
+impl<SCL, SDA> DmaI2c<SCL, SDA> {
+    /// Deadline and timeout handling as in [super::i2c_no_irq::I2cNoIrq::read]
+    pub async fn read(
+        &mut self,
+        address: I2cAddress,
+        buffer: &mut [u8],
+        deadline: Option<TickTime>,
+    ) -> Result<(), I2cError> {
+        let res = before(deadline, self.read_transfer(address, buffer)).await;
+        finish(unsafe { &*I2C1::ptr() }, res)
+    }
+    pub async fn write(
+        &mut self,
+        address: I2cAddress,
+        buffer: &[u8],
+        with_end: bool,
+        deadline: Option<TickTime>,
+    ) -> Result<(), I2cError> {
+        let res = before(deadline, self.write_transfer(address, buffer, with_end)).await;
+        finish(unsafe { &*I2C1::ptr() }, res)
+    }
+    /// Writes `bytes` and reads `buffer` with a repeated START in between, see [super::i2c_no_irq::I2cNoIrq::write_read]
+    pub async fn write_read(
+        &mut self,
+        address: I2cAddress,
+        bytes: &[u8],
+        buffer: &mut [u8],
+        deadline: Option<TickTime>,
+    ) -> Result<(), I2cError> {
+        let res = before(deadline, self.write_read_transfer(address, bytes, buffer)).await;
+        finish(unsafe { &*I2C1::ptr() }, res)
+    }
+
+    async fn read_transfer(
+        &mut self,
+        address: I2cAddress,
+        buffer: &mut [u8],
+    ) -> Result<(), I2cError> {
+        check_len(buffer.len())?;
+        check_idle(self.lines)?;
+        self.transfer(
+            address,
+            true,
+            buffer.as_mut_ptr() as u32,
+            buffer.len(),
+            true,
+        )
+        .await
+    }
+    async fn write_transfer(
+        &mut self,
+        address: I2cAddress,
+        buffer: &[u8],
+        with_end: bool,
+    ) -> Result<(), I2cError> {
+        check_len(buffer.len())?;
+        check_idle(self.lines)?;
+        self.transfer(
+            address,
+            false,
+            buffer.as_ptr() as u32,
+            buffer.len(),
+            with_end,
+        )
+        .await
+    }
+    async fn write_read_transfer(
+        &mut self,
+        address: I2cAddress,
+        bytes: &[u8],
+        buffer: &mut [u8],
+    ) -> Result<(), I2cError> {
+        // both parts are checked up front, so a failing read does not leave the bus stretched after the write
+        check_len(bytes.len())?;
+        check_len(buffer.len())?;
+        check_idle(self.lines)?;
+        if !bytes.is_empty() {
+            self.transfer(
+                address,
+                false,
+                bytes.as_ptr() as u32,
+                bytes.len(),
+                buffer.is_empty(),
+            )
+            .await?;
+            if buffer.is_empty() {
+                return Ok(());
+            }
+        }
+        // ended with TC, so this is a repeated START
+        self.transfer(
+            address,
+            true,
+            buffer.as_mut_ptr() as u32,
+            buffer.len(),
+            true,
+        )
+        .await
+    }
+
+    /// Moves `len` bytes between the bus and the buffer at `mem`, which the caller borrows until the future finished
+    ///
+    /// The PEC byte is sent by the hardware. On reads the DMA stops before it, so it is dropped from RXDR after the
+    /// hardware checked it.
+    async fn transfer(
+        &mut self,
+        address: I2cAddress,
+        read: bool,
+        mem: u32,
+        len: usize,
+        with_end: bool,
+    ) -> Result<(), I2cError> {
+        if len > 0 {
+            self.start_dma(read, mem, len);
+        }
+        let port = unsafe { &*I2C1::ptr() };
+        let pec = pec_len(address, with_end);
+        start_transfer(port, address, read, len, with_end);
+        let res = DmaI2cTransfer {
+            i2c: self,
+            read,
+            dma: len > 0,
+            remaining: len + pec,
+            with_end,
+            done: false,
+        }
+        .await;
+        if res.is_ok() && read && pec > 0 {
+            port.rxdr.read();
+        }
+        res
+    }
+
+    /// Frees a bus blocked by a device holding SDA low, see [super::i2c_no_irq::I2cNoIrq::recover]
+    pub async fn recover(&mut self) -> Result<(), I2cError> {
+        self.stop_dma();
+        let port = unsafe { &*I2C1::ptr() };
+        port.cr1
+            .modify(|r, w| unsafe { w.bits(r.bits() & !CR1_IRQ_MASK) });
+        recover_bus(port, self.lines).await
+    }
+}
+
+impl<SCL, SDA> AsyncI2cBus for DmaI2c<SCL, SDA> {
+    fn read<'a>(
+        &'a mut self,
+        address: I2cAddress,
+        buffer: &'a mut [u8],
+        deadline: Option<TickTime>,
+    ) -> impl Future<Output = Result<(), I2cError>> + 'a {
+        DmaI2c::read(self, address, buffer, deadline)
+    }
+    fn write<'a>(
+        &'a mut self,
+        address: I2cAddress,
+        buffer: &'a [u8],
+        with_end: bool,
+        deadline: Option<TickTime>,
+    ) -> impl Future<Output = Result<(), I2cError>> + 'a {
+        DmaI2c::write(self, address, buffer, with_end, deadline)
+    }
+    fn write_read<'a>(
+        &'a mut self,
+        address: I2cAddress,
+        bytes: &'a [u8],
+        buffer: &'a mut [u8],
+        deadline: Option<TickTime>,
+    ) -> impl Future<Output = Result<(), I2cError>> + 'a {
+        DmaI2c::write_read(self, address, bytes, buffer, deadline)
+    }
+    fn recover(&mut self) -> impl Future<Output = Result<(), I2cError>> + '_ {
+        DmaI2c::recover(self)
+    }
+}
+
+/// A DMA channel moves at most [MAX_TRANSFER] bytes, one I2C transfer is not split over several DMA transfers
+fn check_len(len: usize) -> Result<(), I2cError> {
+    if len > MAX_TRANSFER {
+        Err(I2cError::TooLong)
+    } else {
+        Ok(())
+    }
+}
+
+/// Waits for the end of a transfer started by [DmaI2c::transfer]: STOP with `with_end`, TC otherwise
+///
+/// `remaining` counts the bytes not yet covered by NBYTES. Stops the DMA when dropped, so the buffer is not accessed
+/// after its borrow ended.
+struct DmaI2cTransfer<'a, SCL, SDA> {
+    i2c: &'a mut DmaI2c<SCL, SDA>,
+    read: bool,
+    dma: bool,
+    remaining: usize,
+    with_end: bool,
+    done: bool,
+}
+
+impl<'a, SCL, SDA> Future for DmaI2cTransfer<'a, SCL, SDA> {
+    type Output = Result<(), I2cError>;
+
+    fn poll(
+        mut self: core::pin::Pin<&mut Self>,
+        cx: &mut core::task::Context<'_>,
+    ) -> core::task::Poll<Self::Output> {
+        let port = unsafe { &*I2C1::ptr() };
+        let dma_error = self.i2c.tx.is_event_triggered(Event::TransferError)
+            || self.i2c.rx.is_event_triggered(Event::TransferError);
+        let res = if dma_error {
+            Err(I2cError::Bus)
+        } else if let Some(err) = bus_error(port) {
+            Err(err)
+        } else {
+            let isr = port.isr.read();
+            if isr.tcr().bit_is_set() {
+                self.remaining -= self.remaining.min(MAX_NBYTES);
+                reload(port, self.remaining, self.with_end);
+            }
+            let bus_done = if self.with_end {
+                isr.stopf().is_stop()
+            } else {
+                isr.tc().bit_is_set()
+            };
+            // the DMA reads RXDR right after RXNE, but may lag behind the STOP after the last byte
+            let dma_done = !self.dma
+                || if self.read {
+                    self.i2c.rx.is_event_triggered(Event::TransferComplete)
+                } else {
+                    self.i2c.tx.is_event_triggered(Event::TransferComplete)
+                };
+            if !(bus_done && dma_done) {
+                if !bus_done {
+                    let events = wake_events(port, ISR_STOPF | ISR_TC | ISR_TCR);
+                    listen(port, events, cx.waker());
+                }
+                if !dma_done {
+                    // also catches a transfer error which would stall the bus
+                    I2C1_NOTIFY.subscribe(cx.waker());
+                    let read = self.read;
+                    self.i2c.listen_dma(read);
+                }
+                return Poll::Pending;
+            }
+            if self.with_end {
+                port.icr.write(|w| w.stopcf().clear());
+            }
+            Ok(())
+        };
+        self.done = true;
+        self.i2c.stop_dma();
+        Poll::Ready(res)
+    }
+}
+
+impl<'a, SCL, SDA> Drop for DmaI2cTransfer<'a, SCL, SDA> {
+    fn drop(&mut self) {
+        if !self.done {
+            self.i2c.stop_dma();
+        }
+    }
+}
+
+#[interrupt]
+fn DMA1_CH6() {
+    let dma = unsafe { &*DMA1::ptr() };
+    // flags stay set for the waiting future - only mask the interrupts
+    dma.ch6
+        .cr
+        .modify(|r, w| unsafe { w.bits(r.bits() & !CCR_IRQ_MASK) });
+    I2C1_NOTIFY.notify();
+}
+
+#[interrupt]
+fn DMA1_CH7() {
+    let dma = unsafe { &*DMA1::ptr() };
+    dma.ch7
+        .cr
+        .modify(|r, w| unsafe { w.bits(r.bits() & !CCR_IRQ_MASK) });
+    I2C1_NOTIFY.notify();
+}
@@ -0,0 +1,130 @@
+use super::i2c_irq::I2cIrq;
+use super::i2c_no_irq::I2cNoIrq;
+use super::{I2cAddress, I2cError};
+use embedded_hal_async::i2c::{
+    Error, ErrorKind, ErrorType, I2c, NoAcknowledgeSource, Operation, TenBitAddress,
+};
+use stm32f3xx_hal::i2c::Instance;
+
+impl Error for I2cError {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            I2cError::Arbitration => ErrorKind::ArbitrationLoss,
+            I2cError::Bus => ErrorKind::Bus,
+            I2cError::Nack => ErrorKind::NoAcknowledge(NoAcknowledgeSource::Unknown),
+            I2cError::BusStuck
+            | I2cError::Timeout
+            | I2cError::ClockTimeout
+            | I2cError::Pec
+            | I2cError::TooLong => ErrorKind::Other,
+        }
+    }
+}
+
+impl<T: Instance, SCL, SDA> ErrorType for I2cNoIrq<T, SCL, SDA> {
+    type Error = I2cError;
+}
+
+/// Transfers without deadline, use [I2cNoIrq::transaction] to pass one
+impl<T: Instance, SCL, SDA> I2c for I2cNoIrq<T, SCL, SDA> {
+    async fn read(&mut self, address: u8, read: &mut [u8]) -> Result<(), I2cError> {
+        I2cNoIrq::read(self, address.into(), read, None).await
+    }
+    async fn write(&mut self, address: u8, write: &[u8]) -> Result<(), I2cError> {
+        I2cNoIrq::write(self, address.into(), write, true, None).await
+    }
+    async fn write_read(
+        &mut self,
+        address: u8,
+        write: &[u8],
+        read: &mut [u8],
+    ) -> Result<(), I2cError> {
+        I2cNoIrq::write_read(self, address.into(), write, read, None).await
+    }
+    async fn transaction(
+        &mut self,
+        address: u8,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), I2cError> {
+        I2cNoIrq::transaction(self, address.into(), operations, None).await
+    }
+}
+
+/// 10 bit addresses, transfers without deadline
+impl<T: Instance, SCL, SDA> I2c<TenBitAddress> for I2cNoIrq<T, SCL, SDA> {
+    async fn read(&mut self, address: u16, read: &mut [u8]) -> Result<(), I2cError> {
+        I2cNoIrq::read(self, I2cAddress::ten_bit(address), read, None).await
+    }
+    async fn write(&mut self, address: u16, write: &[u8]) -> Result<(), I2cError> {
+        I2cNoIrq::write(self, I2cAddress::ten_bit(address), write, true, None).await
+    }
+    async fn write_read(
+        &mut self,
+        address: u16,
+        write: &[u8],
+        read: &mut [u8],
+    ) -> Result<(), I2cError> {
+        I2cNoIrq::write_read(self, I2cAddress::ten_bit(address), write, read, None).await
+    }
+    async fn transaction(
+        &mut self,
+        address: u16,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), I2cError> {
+        I2cNoIrq::transaction(self, I2cAddress::ten_bit(address), operations, None).await
+    }
+}
+
+impl<SCL, SDA> ErrorType for I2cIrq<SCL, SDA> {
+    type Error = I2cError;
+}
+
+/// Transfers without deadline, use [I2cIrq::transaction] to pass one
+impl<SCL, SDA> I2c for I2cIrq<SCL, SDA> {
+    async fn read(&mut self, address: u8, read: &mut [u8]) -> Result<(), I2cError> {
+        I2cIrq::read(self, address.into(), read, None).await
+    }
+    async fn write(&mut self, address: u8, write: &[u8]) -> Result<(), I2cError> {
+        I2cIrq::write(self, address.into(), write, true, None).await
+    }
+    async fn write_read(
+        &mut self,
+        address: u8,
+        write: &[u8],
+        read: &mut [u8],
+    ) -> Result<(), I2cError> {
+        I2cIrq::write_read(self, address.into(), write, read, None).await
+    }
+    async fn transaction(
+        &mut self,
+        address: u8,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), I2cError> {
+        I2cIrq::transaction(self, address.into(), operations, None).await
+    }
+}
+
+/// 10 bit addresses, transfers without deadline
+impl<SCL, SDA> I2c<TenBitAddress> for I2cIrq<SCL, SDA> {
+    async fn read(&mut self, address: u16, read: &mut [u8]) -> Result<(), I2cError> {
+        I2cIrq::read(self, I2cAddress::ten_bit(address), read, None).await
+    }
+    async fn write(&mut self, address: u16, write: &[u8]) -> Result<(), I2cError> {
+        I2cIrq::write(self, I2cAddress::ten_bit(address), write, true, None).await
+    }
+    async fn write_read(
+        &mut self,
+        address: u16,
+        write: &[u8],
+        read: &mut [u8],
+    ) -> Result<(), I2cError> {
+        I2cIrq::write_read(self, I2cAddress::ten_bit(address), write, read, None).await
+    }
+    async fn transaction(
+        &mut self,
+        address: u16,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), I2cError> {
+        I2cIrq::transaction(self, I2cAddress::ten_bit(address), operations, None).await
+    }
+}
@@ -0,0 +1,262 @@
+use super::i2c_no_irq::{
+    check_idle, finish, read_transfer, set_clock_timeout, timeout_a, transaction_transfer,
+    write_read_transfer, write_transfer,
+};
+use super::i2c_regs::{
+    I2cRegisters, ISR_ARLO, ISR_BERR, ISR_NACKF, ISR_PECERR, ISR_RXNE, ISR_STOPF, ISR_TC, ISR_TCR,
+    ISR_TIMEOUT, ISR_TXIS,
+};
+use super::recovery::{recover_bus, BusLines, I2cBusPins};
+use super::{AsyncI2cBus, I2cAddress, I2cError};
+use crate::deadline::before;
+use core::future::Future;
+use core::task::Waker;
+use cortex_m::peripheral::NVIC;
+use embedded_hal_async::i2c::Operation;
+use lilos::exec::Notify;
+use lilos::time::TickTime;
+use stm32f3xx_hal::i2c::{I2c, SclPin, SdaPin};
+use stm32f3xx_hal::pac::{i2c1, interrupt, Interrupt, I2C1};
+use stm32f3xx_hal::time::duration::Milliseconds;
+use stm32f3xx_hal::time::rate::Hertz;
+
+pub(super) static I2C1_NOTIFY: Notify = Notify::new();
+
+/// I2C_CR1: TXIE, RXIE, NACKIE, STOPIE, TCIE and ERRIE
+const CR1_TXIE: u32 = 1 << 1;
+const CR1_RXIE: u32 = 1 << 2;
+const CR1_NACKIE: u32 = 1 << 4;
+const CR1_STOPIE: u32 = 1 << 5;
+const CR1_TCIE: u32 = 1 << 6;
+const CR1_ERRIE: u32 = 1 << 7;
+pub(super) const CR1_IRQ_MASK: u32 = 0b1111_0110;
+
+/// Interrupt driven async I2C on I2C1
+///
+/// Same API and transfers as [super::i2c_no_irq::I2cNoIrq], but a pending transfer enables the event (TXIS/RXNE/NACK)
+/// and error interrupts and sleeps until one of them fires. No wake-all task is needed and the executor is free for
+/// other tasks.
+pub struct I2cIrq<SCL, SDA> {
+    i2c: I2c<I2C1, (SCL, SDA)>,
+    lines: Option<BusLines>,
+}
+
+impl<SCL, SDA> I2cIrq<SCL, SDA> {
+    /// Creates the driver from a HAL Instance and unmasks the I2C1 interrupts in the NVIC
+    pub fn new(i2c: I2c<I2C1, (SCL, SDA)>) -> Self
+    where
+        SCL: SclPin<I2C1>,
+        SDA: SdaPin<I2C1>,
+    {
+        unsafe {
+            NVIC::unmask(Interrupt::I2C1_EV_EXTI23);
+            NVIC::unmask(Interrupt::I2C1_ER);
+        }
+        Self { i2c, lines: None }
+    }
+    pub fn release(self) -> I2c<I2C1, (SCL, SDA)> {
+        self.i2c
+    }
+    /// See [super::i2c_no_irq::I2cNoIrq::with_bus_pins]
+    pub fn with_bus_pins(mut self) -> Self
+    where
+        (SCL, SDA): I2cBusPins,
+    {
+        self.lines = Some(BusLines::of::<(SCL, SDA)>());
+        self
+    }
+    /// See [super::i2c_no_irq::I2cNoIrq::enable_clock_timeout]
+    pub fn enable_clock_timeout(&mut self, timeout: Milliseconds, i2cclk: Hertz) {
+        let port = unsafe { &*I2C1::ptr() };
+        set_clock_timeout(port, Some(timeout_a(timeout.0, i2cclk.0)));
+    }
+    pub fn disable_clock_timeout(&mut self) {
+        let port = unsafe { &*I2C1::ptr() };
+        set_clock_timeout(port, None);
+    }
+}
+
+impl<SCL, SDA> I2cIrq<SCL, SDA> {
+    /// Deadline and timeout handling as in [super::i2c_no_irq::I2cNoIrq::read]
+    pub async fn read(
+        &mut self,
+        address: I2cAddress,
+        buffer: &mut [u8],
+        deadline: Option<TickTime>,
+    ) -> Result<(), I2cError> {
+        check_idle(self.lines)?;
+        let regs = &IrqRegisters(self.port());
+        let res = before(deadline, read_transfer(regs, address, buffer)).await;
+        finish(regs, res)
+    }
+    pub async fn write(
+        &mut self,
+        address: I2cAddress,
+        buffer: &[u8],
+        with_end: bool,
+        deadline: Option<TickTime>,
+    ) -> Result<(), I2cError> {
+        check_idle(self.lines)?;
+        let regs = &IrqRegisters(self.port());
+        let res = before(deadline, write_transfer(regs, address, buffer, with_end)).await;
+        finish(regs, res)
+    }
+    /// Writes `bytes` and reads `buffer` with a repeated START in between, see [super::i2c_no_irq::I2cNoIrq::write_read]
+    pub async fn write_read(
+        &mut self,
+        address: I2cAddress,
+        bytes: &[u8],
+        buffer: &mut [u8],
+        deadline: Option<TickTime>,
+    ) -> Result<(), I2cError> {
+        check_idle(self.lines)?;
+        let regs = &IrqRegisters(self.port());
+        let res = before(deadline, write_read_transfer(regs, address, bytes, buffer)).await;
+        finish(regs, res)
+    }
+    /// See [super::i2c_no_irq::I2cNoIrq::transaction]
+    pub async fn transaction(
+        &mut self,
+        address: I2cAddress,
+        operations: &mut [Operation<'_>],
+        deadline: Option<TickTime>,
+    ) -> Result<(), I2cError> {
+        check_idle(self.lines)?;
+        let regs = &IrqRegisters(self.port());
+        let res = before(deadline, transaction_transfer(regs, address, operations)).await;
+        finish(regs, res)
+    }
+
+    /// Frees a bus blocked by a device holding SDA low, see [super::i2c_no_irq::I2cNoIrq::recover]
+    pub async fn recover(&mut self) -> Result<(), I2cError> {
+        let port = unsafe { &*I2C1::ptr() };
+        port.cr1
+            .modify(|r, w| unsafe { w.bits(r.bits() & !CR1_IRQ_MASK) });
+        recover_bus(port, self.lines).await
+    }
+
+    fn port(&mut self) -> &i2c1::RegisterBlock {
+        unsafe { self.i2c.peripheral() }
+    }
+}
+
+impl<SCL, SDA> AsyncI2cBus for I2cIrq<SCL, SDA> {
+    fn read<'a>(
+        &'a mut self,
+        address: I2cAddress,
+        buffer: &'a mut [u8],
+        deadline: Option<TickTime>,
+    ) -> impl Future<Output = Result<(), I2cError>> + 'a {
+        I2cIrq::read(self, address, buffer, deadline)
+    }
+    fn write<'a>(
+        &'a mut self,
+        address: I2cAddress,
+        buffer: &'a [u8],
+        with_end: bool,
+        deadline: Option<TickTime>,
+    ) -> impl Future<Output = Result<(), I2cError>> + 'a {
+        I2cIrq::write(self, address, buffer, with_end, deadline)
+    }
+    fn write_read<'a>(
+        &'a mut self,
+        address: I2cAddress,
+        bytes: &'a [u8],
+        buffer: &'a mut [u8],
+        deadline: Option<TickTime>,
+    ) -> impl Future<Output = Result<(), I2cError>> + 'a {
+        I2cIrq::write_read(self, address, bytes, buffer, deadline)
+    }
+    fn recover(&mut self) -> impl Future<Output = Result<(), I2cError>> + '_ {
+        I2cIrq::recover(self)
+    }
+}
+
+/// Registers the waker and enables the interrupts of the ISR flags in `events`
+///
+/// The ISR disables them again before the task is woken.
+pub(super) fn listen(port: &i2c1::RegisterBlock, events: u32, waker: &Waker) {
+    let mut bits = 0;
+    if events & ISR_TXIS != 0 {
+        bits |= CR1_TXIE;
+    }
+    if events & ISR_RXNE != 0 {
+        bits |= CR1_RXIE;
+    }
+    if events & ISR_NACKF != 0 {
+        bits |= CR1_NACKIE;
+    }
+    if events & ISR_STOPF != 0 {
+        bits |= CR1_STOPIE;
+    }
+    // TCIE also enables the TCR interrupt at the end of a chunk
+    if events & (ISR_TC | ISR_TCR) != 0 {
+        bits |= CR1_TCIE;
+    }
+    if events & (ISR_BERR | ISR_ARLO | ISR_PECERR | ISR_TIMEOUT) != 0 {
+        bits |= CR1_ERRIE;
+    }
+    I2C1_NOTIFY.subscribe(waker);
+    port.cr1.modify(|r, w| unsafe { w.bits(r.bits() | bits) });
+}
+
+/// I2C1 for the transfers of [super::i2c_no_irq], which sleep until the interrupt of the awaited event fires
+struct IrqRegisters<'a>(&'a i2c1::RegisterBlock);
+
+impl<'a> I2cRegisters for IrqRegisters<'a> {
+    fn isr(&self) -> u32 {
+        self.0.isr()
+    }
+    fn flush_txdr(&self) {
+        self.0.flush_txdr();
+    }
+    fn clear(&self, bits: u32) {
+        self.0.clear(bits);
+    }
+    fn cr1(&self) -> u32 {
+        self.0.cr1()
+    }
+    fn write_cr1(&self, bits: u32) {
+        self.0.write_cr1(bits);
+    }
+    fn cr2(&self) -> u32 {
+        self.0.cr2()
+    }
+    fn write_cr2(&self, bits: u32) {
+        self.0.write_cr2(bits);
+    }
+    fn write_timeoutr(&self, bits: u32) {
+        self.0.write_timeoutr(bits);
+    }
+    fn write_oar1(&self, bits: u32) {
+        self.0.write_oar1(bits);
+    }
+    fn write_txdr(&self, byte: u8) {
+        self.0.write_txdr(byte);
+    }
+    fn read_rxdr(&self) -> u8 {
+        self.0.read_rxdr()
+    }
+    fn listen(&self, events: u32, waker: &Waker) {
+        listen(self.0, events, waker);
+    }
+}
+
+/// Also serves [super::dma_i2c::DmaI2c]
+#[interrupt]
+fn I2C1_EV_EXTI23() {
+    on_i2c1_irq();
+}
+
+#[interrupt]
+fn I2C1_ER() {
+    on_i2c1_irq();
+}
+
+fn on_i2c1_irq() {
+    let i2c = unsafe { &*I2C1::ptr() };
+    // TXIS, RXNE, TC and NACKF stay set until served by the task - disable them until the next listen
+    i2c.cr1
+        .modify(|r, w| unsafe { w.bits(r.bits() & !CR1_IRQ_MASK) });
+    I2C1_NOTIFY.notify();
+}
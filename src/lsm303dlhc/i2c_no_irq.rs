@@ -1,11 +1,25 @@
+use super::i2c_regs::{
+    I2cRegisters, CR1_PE, CR1_PECEN, CR2_ADD10, CR2_AUTOEND, CR2_NBYTES_MASK, CR2_NBYTES_SHIFT,
+    CR2_PECBYTE, CR2_RD_WRN, CR2_RELOAD, CR2_SADD_MASK, CR2_START, ISR_ARLO, ISR_BERR, ISR_NACKF,
+    ISR_PECERR, ISR_RXNE, ISR_STOPF, ISR_TC, ISR_TCR, ISR_TIMEOUT, ISR_TXIS,
+};
+use super::recovery::{recover_bus, BusLines, I2cBusPins};
+use super::{AsyncI2cBus, I2cAddress, I2cError};
+use crate::deadline::before;
 use core::future::Future;
-use stm32f3xx_hal::i2c::{Error, I2c, Instance, SclPin, SdaPin};
+use embedded_hal_async::i2c::Operation;
+use lilos::time::TickTime;
+use stm32f3xx_hal::i2c::{I2c, Instance, SclPin, SdaPin};
+use stm32f3xx_hal::pac::i2c1;
+use stm32f3xx_hal::time::duration::Milliseconds;
+use stm32f3xx_hal::time::rate::Hertz;
 
 /// We build on top of [stm32f3xx_hal::i2c::I2c] so we can reuse all the enable and clock selection stuff
 /// We only implement async read/write on top
 pub struct I2cNoIrq<T: Instance, SCL, SDA> {
     i2c: I2c<T, (SCL, SDA)>,
-    adr: u8,
+    /// Set by [I2cNoIrq::with_bus_pins]
+    lines: Option<BusLines>,
 }
 
 impl<T: Instance, SCL, SDA> I2cNoIrq<T, SCL, SDA> {
@@ -13,143 +27,828 @@ impl<T: Instance, SCL, SDA> I2cNoIrq<T, SCL, SDA> {
     ///
     /// We use the HAL I2c to reuse all the clock selection and enabling work done by the new
     /// function of the [stm32f3xx_hal::i2c::I2c] struct.
-    pub fn new(i2c: I2c<T, (SCL, SDA)>, adr: u8) -> Self
+    pub fn new(i2c: I2c<T, (SCL, SDA)>) -> Self
     where
         SCL: SclPin<T>,
         SDA: SdaPin<T>,
     {
-        Self { i2c, adr }
+        Self { i2c, lines: None }
     }
+    pub fn release(self) -> I2c<T, (SCL, SDA)> {
+        self.i2c
+    }
+    /// Checks SDA before every transfer and lets [Self::recover] clock out a device holding it low
+    pub fn with_bus_pins(mut self) -> Self
+    where
+        (SCL, SDA): I2cBusPins,
+    {
+        self.lines = Some(BusLines::of::<(SCL, SDA)>());
+        self
+    }
+    /// Fails transfers with [I2cError::ClockTimeout] if a device holds SCL low longer than `timeout`
+    ///
+    /// `i2cclk` is the kernel clock of the peripheral (HSI unless selected otherwise in RCC_CFGR3). The timeout is
+    /// rounded up to a multiple of 2048 cycles and limited to 4096 of them.
+    pub fn enable_clock_timeout(&mut self, timeout: Milliseconds, i2cclk: Hertz) {
+        set_clock_timeout(self.regs(), Some(timeout_a(timeout.0, i2cclk.0)));
+    }
+    pub fn disable_clock_timeout(&mut self) {
+        set_clock_timeout(self.regs(), None);
+    }
+    fn regs(&mut self) -> &i2c1::RegisterBlock {
+        unsafe { self.i2c.peripheral() }
+    }
+}
+
+/// I2C_TIMEOUTR: TIMEOUTA[11:0] and TIMOUTEN. TIDLE stays 0 to detect SCL low.
+const TIMEOUTR_TIMEOUTA_MAX: u32 = 0xFFF;
+const TIMEOUTR_TIMOUTEN: u32 = 1 << 15;
+
+/// TIMEOUTA for a SCL low timeout of at least `timeout_ms`: tTIMEOUT = (TIMEOUTA + 1) * 2048 * tI2CCLK
+pub(super) fn timeout_a(timeout_ms: u32, i2cclk: u32) -> u32 {
+    let cycles = timeout_ms as u64 * i2cclk as u64 / 1000;
+    let periods = cycles.div_ceil(2048);
+    periods.saturating_sub(1).min(TIMEOUTR_TIMEOUTA_MAX as u64) as u32
+}
+
+/// Programs TIMEOUTR with `timeout_a` or disables the timeout
+pub(super) fn set_clock_timeout<R: I2cRegisters>(regs: &R, timeout_a: Option<u32>) {
+    // TIMEOUTA must only be changed while TIMOUTEN is cleared
+    regs.write_timeoutr(0);
+    if let Some(timeout_a) = timeout_a {
+        regs.write_timeoutr(timeout_a);
+        regs.write_timeoutr(timeout_a | TIMEOUTR_TIMOUTEN);
+    }
+}
+
+/// Aborts a pending transfer: clearing PE releases the lines and resets the state machine and status flags
+pub(super) fn software_reset<R: I2cRegisters>(regs: &R) {
+    regs.write_cr1(regs.cr1() & !CR1_PE);
+    // PE must stay low for 3 APB cycles, the read back takes long enough
+    while regs.cr1() & CR1_PE != 0 {}
+    regs.write_cr1(regs.cr1() | CR1_PE);
+}
+
+/// Maps a transfer which missed its deadline to [I2cError::Timeout] and resets the peripheral after a timeout
+pub(super) fn finish<R: I2cRegisters>(
+    regs: &R,
+    res: Option<Result<(), I2cError>>,
+) -> Result<(), I2cError> {
+    let res = res.unwrap_or(Err(I2cError::Timeout));
+    if let Err(I2cError::Timeout | I2cError::ClockTimeout) = res {
+        software_reset(regs);
+    }
+    res
+}
+
+/// Checks and clears the error flags of a pending transfer
+///
+/// A NACK or PEC mismatch is reported with the STOP the hardware generates after it. Until then the transfer stays
+/// pending and only waits for STOPF (see [wake_events]), a STOP which never shows up is bounded by the deadline or the
+/// clock timeout like every other wait.
+pub(super) fn bus_error<R: I2cRegisters>(regs: &R) -> Option<I2cError> {
+    let isr = regs.isr();
+    if isr & ISR_TIMEOUT != 0 {
+        regs.clear(ISR_TIMEOUT);
+        Some(I2cError::ClockTimeout)
+    } else if isr & ISR_ARLO != 0 {
+        regs.clear(ISR_ARLO);
+        Some(I2cError::Arbitration)
+    } else if isr & ISR_BERR != 0 {
+        regs.clear(ISR_BERR);
+        Some(I2cError::Bus)
+    } else if isr & ISR_STOPF == 0 {
+        None
+    } else if isr & ISR_PECERR != 0 {
+        // raised with the PEC byte, the last of a read, which the master ends with NACK and STOP
+        if isr & ISR_RXNE != 0 {
+            regs.read_rxdr();
+        }
+        regs.clear(ISR_PECERR | ISR_STOPF);
+        Some(I2cError::Pec)
+    } else if isr & ISR_NACKF != 0 {
+        regs.clear(ISR_NACKF | ISR_STOPF);
+        Some(I2cError::Nack)
+    } else {
+        None
+    }
+}
+
+/// ISR flags checked by [bus_error]
+const ISR_ERRORS: u32 = ISR_TIMEOUT | ISR_ARLO | ISR_BERR | ISR_PECERR | ISR_NACKF;
+
+/// The ISR flags a pending transfer waits for: `events` and the errors, or only the STOP after a NACK or PEC error
+pub(super) fn wake_events<R: I2cRegisters>(regs: &R, events: u32) -> u32 {
+    if regs.isr() & (ISR_NACKF | ISR_PECERR) != 0 {
+        ISR_STOPF | ISR_TIMEOUT | ISR_ARLO | ISR_BERR
+    } else {
+        events | ISR_ERRORS
+    }
+}
+
+/// Lets `regs` wake the task on the [wake_events] of `events`, see [I2cRegisters::listen]
+fn pending<R: I2cRegisters, T>(
+    regs: &R,
+    events: u32,
+    cx: &mut core::task::Context<'_>,
+) -> core::task::Poll<T> {
+    regs.listen(wake_events(regs, events), cx.waker());
+    core::task::Poll::Pending
+}
+
+/// NBYTES is 8 bit: longer transfers are split into chunks with RELOAD
+pub(super) const MAX_NBYTES: usize = 255;
+
+/// NBYTES, RELOAD and AUTOEND for a transfer of which `remaining` bytes are left
+///
+/// Only the last chunk ends with STOP (if `with_end`) or TC, the others with TCR.
+fn length_bits(remaining: usize, with_end: bool) -> u32 {
+    let mut bits = (remaining.min(MAX_NBYTES) as u32) << CR2_NBYTES_SHIFT;
+    if remaining > MAX_NBYTES {
+        bits |= CR2_RELOAD;
+    }
+    if with_end {
+        bits |= CR2_AUTOEND;
+    }
+    bits
+}
+
+/// The PEC byte which ends a transfer with STOP to a device with [I2cAddress::with_pec], 0 or 1
+pub(super) fn pec_len(address: I2cAddress, with_end: bool) -> usize {
+    (address.pec() && with_end) as usize
+}
+
+/// Programs CR2 for a transfer of `len` data bytes and generates a (repeated) START
+///
+/// NBYTES includes the PEC byte of [pec_len], which the caller counts as `rest` of the last buffer. The hardware
+/// sends it after the written data, on reads it is the last byte received.
+pub(super) fn start_transfer<R: I2cRegisters>(
+    regs: &R,
+    address: I2cAddress,
+    read: bool,
+    len: usize,
+    with_end: bool,
+) {
+    let keep = regs.cr2()
+        & !(CR2_SADD_MASK
+            | CR2_RD_WRN
+            | CR2_ADD10
+            | CR2_NBYTES_MASK
+            | CR2_RELOAD
+            | CR2_AUTOEND
+            | CR2_PECBYTE);
+    let direction = if read { CR2_RD_WRN } else { 0 };
+    // HEAD10R stays 0: a read after a write repeats the complete 10 bit address
+    let sadd = if address.is_ten_bit() {
+        address.address() as u32 | CR2_ADD10
+    } else {
+        (address.address() as u32) << 1
+    };
+    let pec = pec_len(address, with_end);
+    // PECEN follows the device, so transfers to devices without PEC never run with the PEC unit enabled
+    let (pecen, pec_byte) = if pec > 0 {
+        (CR1_PECEN, CR2_PECBYTE)
+    } else {
+        (0, 0)
+    };
+    let cr1 = regs.cr1();
+    if cr1 & CR1_PECEN != pecen {
+        regs.write_cr1((cr1 & !CR1_PECEN) | pecen);
+    }
+    let length = length_bits(len + pec, with_end);
+    // a STOPF or NACKF left by a cancelled transfer would end this one before its own STOP
+    regs.clear(ISR_STOPF | ISR_NACKF);
+    regs.write_cr2(keep | sadd | direction | length | pec_byte | CR2_START);
+}
+
+/// Continues a transfer after TCR with the next chunk
+pub(super) fn reload<R: I2cRegisters>(regs: &R, remaining: usize, with_end: bool) {
+    let keep = regs.cr2() & !(CR2_NBYTES_MASK | CR2_RELOAD | CR2_AUTOEND);
+    regs.write_cr2(keep | length_bits(remaining, with_end));
 }
-struct AsyncI2cWrite<'a, T: Instance, SCL, SDA> {
-    i2c: &'a mut I2cNoIrq<T, SCL, SDA>,
+
+/// Writes `buf`, which may be followed by `rest` bytes of the same transfer in other buffers
+struct AsyncI2cWrite<'a, R: I2cRegisters> {
+    regs: &'a R,
     buf: &'a [u8],
-    cnt: u8,
+    cnt: usize,
+    rest: usize,
+    with_end: bool,
 }
-impl<'a, T: Instance, SCL, SDA> Future for AsyncI2cWrite<'a, T, SCL, SDA> {
-    type Output = Result<(), Error>;
+impl<'a, R: I2cRegisters> Future for AsyncI2cWrite<'a, R> {
+    type Output = Result<(), I2cError>;
 
     fn poll(
         mut self: core::pin::Pin<&mut Self>,
-        _cx: &mut core::task::Context<'_>,
+        cx: &mut core::task::Context<'_>,
     ) -> core::task::Poll<Self::Output> {
-        let cnt = self.cnt as usize;
-        let byte = self.buf[cnt];
-        let port = &mut unsafe { self.i2c.i2c.peripheral() };
-        if true == port.isr.read().txis().is_empty() {
-            port.txdr.write(|w| w.txdata().bits(byte));
+        let regs = self.regs;
+        let isr = regs.isr();
+        if isr & ISR_TXIS != 0 {
+            regs.write_txdr(self.buf[self.cnt]);
             self.cnt += 1;
-            if self.cnt == self.buf.len() as u8 {
-                core::task::Poll::Ready(Ok(()))
-            } else {
-                core::task::Poll::Pending
-            }
-        } else {
-            let isr = port.isr.read();
-            let icr = &port.icr;
-
-            if isr.arlo().is_lost() {
-                icr.write(|w| w.arlocf().clear());
-                return core::task::Poll::Ready(Err(Error::Arbitration));
-            } else if isr.berr().is_error() {
-                icr.write(|w| w.berrcf().clear());
-                return core::task::Poll::Ready(Err(Error::Bus));
-            } else if isr.nackf().is_nack() {
-                while port.isr.read().stopf().is_no_stop() {}
-                icr.write(|w| w.nackcf().clear());
-                icr.write(|w| w.stopcf().clear());
-                return core::task::Poll::Ready(Err(Error::Nack));
-            } else {
-                return core::task::Poll::Pending;
+            if self.cnt == self.buf.len() {
+                return core::task::Poll::Ready(Ok(()));
             }
+        } else if isr & ISR_TCR != 0 {
+            reload(regs, self.buf.len() - self.cnt + self.rest, self.with_end);
+        } else if let Some(err) = bus_error(regs) {
+            return core::task::Poll::Ready(Err(err));
         }
+        pending(regs, ISR_TXIS | ISR_TCR, cx)
     }
 }
-struct AsyncI2c<'a, T: Instance, SCL, SDA> {
-    i2c: &'a mut I2cNoIrq<T, SCL, SDA>,
+/// Reads `buf`, see [AsyncI2cWrite] for `rest`
+struct AsyncI2c<'a, R: I2cRegisters> {
+    regs: &'a R,
     buf: &'a mut [u8],
-    cnt: u8,
+    cnt: usize,
+    rest: usize,
+    with_end: bool,
 }
-impl<'a, T: Instance, SCL, SDA> Future for AsyncI2c<'a, T, SCL, SDA> {
-    type Output = Result<(), Error>;
+impl<'a, R: I2cRegisters> Future for AsyncI2c<'a, R> {
+    type Output = Result<(), I2cError>;
 
     fn poll(
         mut self: core::pin::Pin<&mut Self>,
-        _cx: &mut core::task::Context<'_>,
+        cx: &mut core::task::Context<'_>,
     ) -> core::task::Poll<Self::Output> {
-        let port = &mut unsafe { self.i2c.i2c.peripheral() };
-        if true == port.isr.read().rxne().is_not_empty() {
-            let byte = port.rxdr.read().rxdata().bits();
-            let cnt = self.cnt as usize;
-            self.buf[cnt] = byte;
+        let regs = self.regs;
+        let isr = regs.isr();
+        if isr & ISR_RXNE != 0 {
+            let cnt = self.cnt;
+            self.buf[cnt] = regs.read_rxdr();
             self.cnt += 1;
-            if self.cnt == self.buf.len() as u8 {
-                core::task::Poll::Ready(Ok(()))
-            } else {
-                core::task::Poll::Pending
+            if self.cnt == self.buf.len() {
+                return core::task::Poll::Ready(Ok(()));
             }
+        } else if isr & ISR_TCR != 0 {
+            reload(regs, self.buf.len() - self.cnt + self.rest, self.with_end);
+        } else if let Some(err) = bus_error(regs) {
+            return core::task::Poll::Ready(Err(err));
+        }
+        pending(regs, ISR_RXNE | ISR_TCR, cx)
+    }
+}
+
+/// Waits for TC: all bytes of a transfer without AUTOEND are acknowledged and SCL is stretched until the next START
+/// or STOP
+///
+/// With `stop` it waits for the STOP of an AUTOEND transfer instead and clears STOPF. Every transfer ends with one of
+/// them, so the next transfer neither starts into a running one nor sees a STOPF which is not its own.
+struct AsyncI2cTransferComplete<'a, R: I2cRegisters> {
+    regs: &'a R,
+    stop: bool,
+}
+impl<'a, R: I2cRegisters> Future for AsyncI2cTransferComplete<'a, R> {
+    type Output = Result<(), I2cError>;
+
+    fn poll(
+        self: core::pin::Pin<&mut Self>,
+        cx: &mut core::task::Context<'_>,
+    ) -> core::task::Poll<Self::Output> {
+        // errors first: a NACK is followed by STOP as well
+        if let Some(err) = bus_error(self.regs) {
+            core::task::Poll::Ready(Err(err))
+        } else if transfer_complete(self.regs, self.stop) {
+            core::task::Poll::Ready(Ok(()))
         } else {
-            let isr = port.isr.read();
-            let icr = &port.icr;
-
-            if isr.arlo().is_lost() {
-                icr.write(|w| w.arlocf().clear());
-                return core::task::Poll::Ready(Err(Error::Arbitration));
-            } else if isr.berr().is_error() {
-                icr.write(|w| w.berrcf().clear());
-                return core::task::Poll::Ready(Err(Error::Bus));
-            } else if isr.nackf().is_nack() {
-                while port.isr.read().stopf().is_no_stop() {}
-                icr.write(|w| w.nackcf().clear());
-                icr.write(|w| w.stopcf().clear());
-                return core::task::Poll::Ready(Err(Error::Nack));
-            } else {
-                return core::task::Poll::Pending;
-            }
+            let event = if self.stop { ISR_STOPF } else { ISR_TC };
+            pending(self.regs, event, cx)
         }
     }
 }
 
-impl<T: Instance, SCL, SDA> I2cNoIrq<T, SCL, SDA> {
-    pub async fn read(&mut self, buffer: &mut [u8]) -> Result<(), Error> {
-        // start transfer
-        let p = unsafe { self.i2c.peripheral() };
-
-        p.cr2.modify(|_, w| {
-            w.add10().bit7();
-            w.sadd().bits((self.adr << 1) as u16);
-            w.rd_wrn().read();
-            w.start().start();
-            w.nbytes().bits(buffer.len() as u8);
-            w.reload().completed().autoend().automatic()
-        });
-        // poll isr
+/// Checks TC or, with `stop`, checks and clears STOPF
+fn transfer_complete<R: I2cRegisters>(regs: &R, stop: bool) -> bool {
+    if !stop {
+        regs.isr() & ISR_TC != 0
+    } else if regs.isr() & ISR_STOPF != 0 {
+        regs.clear(ISR_STOPF);
+        true
+    } else {
+        false
+    }
+}
+
+/// Direction, end and byte count of the operations from `first` on which form one transfer
+fn next_run(operations: &[Operation<'_>], first: usize) -> (bool, usize, usize) {
+    let read = matches!(operations[first], Operation::Read(_));
+    let mut end = first;
+    let mut len = 0;
+    while end < operations.len() {
+        match &operations[end] {
+            Operation::Read(buf) if read => len += buf.len(),
+            Operation::Write(buf) if !read => len += buf.len(),
+            _ => break,
+        }
+        end += 1;
+    }
+    (read, end, len)
+}
+
+/// Fails with [I2cError::BusStuck] if a device holds SDA low before a transfer, without `lines` the bus is not checked
+pub(super) fn check_idle(lines: Option<BusLines>) -> Result<(), I2cError> {
+    lines.map_or(Ok(()), |lines| lines.check_idle())
+}
+
+/// Reads `buffer` from the device at `address` and returns after the STOP
+pub(super) async fn read_transfer<R: I2cRegisters>(
+    regs: &R,
+    address: I2cAddress,
+    buffer: &mut [u8],
+) -> Result<(), I2cError> {
+    let pec = pec_len(address, true);
+    start_transfer(regs, address, true, buffer.len(), true);
+    if !buffer.is_empty() {
         AsyncI2c {
-            i2c: self,
+            regs,
             buf: buffer,
             cnt: 0,
+            rest: pec,
+            with_end: true,
         }
-        .await
-    }
-    pub async fn write(&mut self, buffer: &[u8], with_end: bool) -> Result<(), Error> {
-        let p = unsafe { self.i2c.peripheral() };
-        p.cr2.modify(|_, w| {
-            w.add10().bit7();
-            w.sadd().bits((self.adr << 1) as u16);
-            w.rd_wrn().write();
-            w.start().start();
-            w.nbytes().bits(buffer.len() as u8);
-            if with_end {
-                w.reload().completed().autoend().automatic()
-            } else {
-                w.reload().completed().autoend().software()
-            }
-        });
+        .await?;
+    }
+    if pec > 0 {
+        check_pec(regs).await
+    } else {
+        AsyncI2cTransferComplete { regs, stop: true }.await
+    }
+}
+
+/// Receives the PEC byte which ends a read, a mismatch fails with [I2cError::Pec]
+async fn check_pec<R: I2cRegisters>(regs: &R) -> Result<(), I2cError> {
+    AsyncI2c {
+        regs,
+        buf: &mut [0],
+        cnt: 0,
+        rest: 0,
+        with_end: true,
+    }
+    .await?;
+    // PECERR is checked before STOPF
+    AsyncI2cTransferComplete { regs, stop: true }.await
+}
+
+/// Writes `buffer` to the device at `address`, only the address with an empty `buffer`
+///
+/// Returns after the STOP with `with_end` or TC without, so the bus is idle or stretched for the next START.
+pub(super) async fn write_transfer<R: I2cRegisters>(
+    regs: &R,
+    address: I2cAddress,
+    buffer: &[u8],
+    with_end: bool,
+) -> Result<(), I2cError> {
+    start_transfer(regs, address, false, buffer.len(), with_end);
+    if !buffer.is_empty() {
         AsyncI2cWrite {
-            i2c: self,
+            regs,
             buf: buffer,
             cnt: 0,
+            rest: pec_len(address, with_end),
+            with_end,
         }
-        .await
+        .await?;
+    }
+    AsyncI2cTransferComplete {
+        regs,
+        stop: with_end,
+    }
+    .await
+}
+
+/// Writes `bytes` and reads `buffer` with a repeated START in between
+pub(super) async fn write_read_transfer<R: I2cRegisters>(
+    regs: &R,
+    address: I2cAddress,
+    bytes: &[u8],
+    buffer: &mut [u8],
+) -> Result<(), I2cError> {
+    if !bytes.is_empty() {
+        start_transfer(regs, address, false, bytes.len(), buffer.is_empty());
+        AsyncI2cWrite {
+            regs,
+            buf: bytes,
+            cnt: 0,
+            rest: pec_len(address, buffer.is_empty()),
+            with_end: buffer.is_empty(),
+        }
+        .await?;
+        AsyncI2cTransferComplete {
+            regs,
+            stop: buffer.is_empty(),
+        }
+        .await?;
+        if buffer.is_empty() {
+            return Ok(());
+        }
+    }
+    // with TC set this is a repeated START
+    read_transfer(regs, address, buffer).await
+}
+
+/// Runs `operations` as described in [I2cNoIrq::transaction]
+pub(super) async fn transaction_transfer<R: I2cRegisters>(
+    regs: &R,
+    address: I2cAddress,
+    operations: &mut [Operation<'_>],
+) -> Result<(), I2cError> {
+    let mut first = 0;
+    while first < operations.len() {
+        let (read, end, len) = next_run(operations, first);
+        let with_end = end == operations.len();
+        // with TC set from the previous run this is a repeated START
+        start_transfer(regs, address, read, len, with_end);
+        let pec = pec_len(address, with_end);
+        let mut rest = len + pec;
+        for operation in operations[first..end].iter_mut() {
+            match operation {
+                Operation::Read(buf) if !buf.is_empty() => {
+                    rest -= buf.len();
+                    AsyncI2c {
+                        regs,
+                        buf,
+                        cnt: 0,
+                        rest,
+                        with_end,
+                    }
+                    .await?;
+                }
+                Operation::Write(buf) if !buf.is_empty() => {
+                    rest -= buf.len();
+                    AsyncI2cWrite {
+                        regs,
+                        buf,
+                        cnt: 0,
+                        rest,
+                        with_end,
+                    }
+                    .await?;
+                }
+                _ => {}
+            }
+        }
+        if read && pec > 0 {
+            check_pec(regs).await?;
+        } else {
+            AsyncI2cTransferComplete {
+                regs,
+                stop: with_end,
+            }
+            .await?;
+        }
+        first = end;
+    }
+    Ok(())
+}
+
+impl<T: Instance, SCL, SDA> I2cNoIrq<T, SCL, SDA> {
+    /// Reads `buffer` from the device at `address`
+    ///
+    /// `address` also selects 7 or 10 bit addressing and whether the transfer ends with a PEC byte. Fails with
+    /// [I2cError::Timeout] if the transfer did not finish before `deadline`, the peripheral is reset then.
+    pub async fn read(
+        &mut self,
+        address: I2cAddress,
+        buffer: &mut [u8],
+        deadline: Option<TickTime>,
+    ) -> Result<(), I2cError> {
+        check_idle(self.lines)?;
+        let regs = self.regs();
+        let res = before(deadline, read_transfer(regs, address, buffer)).await;
+        finish(regs, res)
+    }
+    /// Writes `buffer` to the device at `address`, without `with_end` no STOP is generated. Deadline as in [Self::read].
+    pub async fn write(
+        &mut self,
+        address: I2cAddress,
+        buffer: &[u8],
+        with_end: bool,
+        deadline: Option<TickTime>,
+    ) -> Result<(), I2cError> {
+        check_idle(self.lines)?;
+        let regs = self.regs();
+        let res = before(deadline, write_transfer(regs, address, buffer, with_end)).await;
+        finish(regs, res)
+    }
+    /// Writes `bytes` and reads `buffer` in one transfer with a repeated START and no STOP in between
+    ///
+    /// Same semantics as [embedded_hal::blocking::i2c::WriteRead], e.g. to read registers after writing their address.
+    /// Deadline as in [Self::read].
+    pub async fn write_read(
+        &mut self,
+        address: I2cAddress,
+        bytes: &[u8],
+        buffer: &mut [u8],
+        deadline: Option<TickTime>,
+    ) -> Result<(), I2cError> {
+        check_idle(self.lines)?;
+        let regs = self.regs();
+        let res = before(deadline, write_read_transfer(regs, address, bytes, buffer)).await;
+        finish(regs, res)
+    }
+    /// Runs the operations of [embedded_hal_async::i2c::I2c::transaction]
+    ///
+    /// Adjacent operations of the same direction form one transfer without START in between, a change of direction
+    /// is a repeated START and the last operation ends with STOP. Deadline as in [Self::read].
+    pub async fn transaction(
+        &mut self,
+        address: I2cAddress,
+        operations: &mut [Operation<'_>],
+        deadline: Option<TickTime>,
+    ) -> Result<(), I2cError> {
+        check_idle(self.lines)?;
+        let regs = self.regs();
+        let res = before(deadline, transaction_transfer(regs, address, operations)).await;
+        finish(regs, res)
+    }
+
+    /// Frees a bus blocked by a device holding SDA low
+    ///
+    /// Disables the peripheral, clocks SCL up to 9 times as GPIO until SDA is released, generates a STOP and enables
+    /// the peripheral again, which also resets its state. Fails with [I2cError::BusStuck] if SDA is still low.
+    /// Without [Self::with_bus_pins] only the peripheral is reset.
+    pub async fn recover(&mut self) -> Result<(), I2cError> {
+        let lines = self.lines;
+        recover_bus(self.regs(), lines).await
+    }
+}
+
+impl<T: Instance, SCL, SDA> AsyncI2cBus for I2cNoIrq<T, SCL, SDA> {
+    fn read<'a>(
+        &'a mut self,
+        address: I2cAddress,
+        buffer: &'a mut [u8],
+        deadline: Option<TickTime>,
+    ) -> impl Future<Output = Result<(), I2cError>> + 'a {
+        I2cNoIrq::read(self, address, buffer, deadline)
+    }
+    fn write<'a>(
+        &'a mut self,
+        address: I2cAddress,
+        buffer: &'a [u8],
+        with_end: bool,
+        deadline: Option<TickTime>,
+    ) -> impl Future<Output = Result<(), I2cError>> + 'a {
+        I2cNoIrq::write(self, address, buffer, with_end, deadline)
+    }
+    fn write_read<'a>(
+        &'a mut self,
+        address: I2cAddress,
+        bytes: &'a [u8],
+        buffer: &'a mut [u8],
+        deadline: Option<TickTime>,
+    ) -> impl Future<Output = Result<(), I2cError>> + 'a {
+        I2cNoIrq::write_read(self, address, bytes, buffer, deadline)
+    }
+    fn recover(&mut self) -> impl Future<Output = Result<(), I2cError>> + '_ {
+        I2cNoIrq::recover(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::sim_i2c::{block_on, SimFault, SimI2c};
+    use super::*;
+
+    const POLLS: usize = 10_000;
+    const ACCEL: I2cAddress = I2cAddress::seven_bit(0x19);
+    const MAGNETO: I2cAddress = I2cAddress::seven_bit(0x1E);
+    const ABSENT: I2cAddress = I2cAddress::seven_bit(0x42);
+
+    #[test]
+    fn acknowledged_write_ends_with_stop() {
+        let sim = SimI2c::new();
+        let res = block_on(write_transfer(&sim, MAGNETO, &[0x00, 0x9C], true), POLLS);
+        assert_eq!(res, Ok(()));
+        let (written, len) = sim.written();
+        assert_eq!(&written[..len], &[0x00, 0x9C]);
+        assert!(sim.idle());
+    }
+
+    #[test]
+    fn consecutive_writes_on_a_bus_with_pins() {
+        let sim = SimI2c::new();
+        let lines = Some(sim.lines());
+        for bytes in [[0x20, 0x57], [0x23, 0x08]] {
+            // the previous STOP is on the bus before the next transfer starts
+            assert_eq!(check_idle(lines), Ok(()));
+            let res = block_on(write_transfer(&sim, ACCEL, &bytes, true), POLLS);
+            assert_eq!(res, Ok(()));
+            assert_eq!(sim.isr() & ISR_STOPF, 0);
+        }
+        let (written, len) = sim.written();
+        assert_eq!(&written[..len], &[0x20, 0x57, 0x23, 0x08]);
+        assert_eq!(sim.starts(), 2);
+        assert!(sim.idle());
+    }
+
+    #[test]
+    fn stale_stop_does_not_end_the_next_transfer() {
+        let sim = SimI2c::new();
+        let lines = Some(sim.lines());
+        // address only write whose future was dropped before it consumed the STOP
+        start_transfer(&sim, ACCEL, false, 0, true);
+        while sim.isr() & ISR_STOPF == 0 {}
+        let res = block_on(write_transfer(&sim, ACCEL, &[0x20, 0x57], true), POLLS);
+        assert_eq!(res, Ok(()));
+        assert_eq!(check_idle(lines), Ok(()));
+        assert_eq!(sim.isr() & ISR_STOPF, 0);
+    }
+
+    #[test]
+    fn address_nack() {
+        let sim = SimI2c::new();
+        sim.nack_address();
+        let res = block_on(read_transfer(&sim, ABSENT, &mut [0; 2]), POLLS);
+        assert_eq!(res, Err(I2cError::Nack));
+        let res = block_on(write_transfer(&sim, ABSENT, &[], true), POLLS);
+        assert_eq!(res, Err(I2cError::Nack));
+    }
+
+    #[test]
+    fn data_nack_stops_the_write() {
+        let sim = SimI2c::new();
+        sim.fault_at(1, SimFault::Nack);
+        let res = block_on(write_transfer(&sim, MAGNETO, &[1, 2, 3], true), POLLS);
+        assert_eq!(res, Err(I2cError::Nack));
+        assert_eq!(sim.written().1, 2);
+    }
+
+    #[test]
+    fn nack_waits_for_the_stop() {
+        let sim = SimI2c::new();
+        sim.nack_address();
+        let res = block_on(write_transfer(&sim, ABSENT, &[1], true), POLLS);
+        assert_eq!(res, Err(I2cError::Nack));
+        // after the NACK only the STOP is awaited, another NACK interrupt would fire right away
+        assert_eq!(sim.listened() & (ISR_NACKF | ISR_STOPF), ISR_STOPF);
+        assert_eq!(sim.isr() & (ISR_NACKF | ISR_STOPF), 0);
+    }
+
+    #[test]
+    fn arbitration_loss() {
+        let sim = SimI2c::new();
+        sim.fault_at(0, SimFault::Arbitration);
+        let res = block_on(write_transfer(&sim, MAGNETO, &[1, 2], true), POLLS);
+        assert_eq!(res, Err(I2cError::Arbitration));
+    }
+
+    #[test]
+    fn bus_error_while_reading() {
+        let sim = SimI2c::new();
+        sim.respond(&[1, 2, 3]);
+        sim.fault_at(2, SimFault::Bus);
+        let mut buf = [0; 3];
+        let res = block_on(read_transfer(&sim, ACCEL, &mut buf), POLLS);
+        assert_eq!(res, Err(I2cError::Bus));
+        assert_eq!(buf, [1, 2, 0]);
+    }
+
+    #[test]
+    fn clock_timeout_resets_the_peripheral() {
+        let sim = SimI2c::new();
+        sim.fault_at(0, SimFault::ClockTimeout);
+        let res = block_on(write_transfer(&sim, MAGNETO, &[1, 2], true), POLLS);
+        assert_eq!(res, Err(I2cError::ClockTimeout));
+        assert_eq!(finish(&sim, Some(res)), Err(I2cError::ClockTimeout));
+        assert_eq!(sim.cr1() & CR1_PE, CR1_PE);
+        assert_eq!(sim.isr(), 0);
+    }
+
+    #[test]
+    fn write_read_uses_repeated_start() {
+        let sim = SimI2c::new();
+        sim.respond(&[0x12, 0x34]);
+        let mut buf = [0; 2];
+        let res = block_on(write_read_transfer(&sim, ACCEL, &[0xA8], &mut buf), POLLS);
+        assert_eq!(res, Ok(()));
+        assert_eq!(buf, [0x12, 0x34]);
+        assert_eq!(sim.starts(), 2);
+        assert!(sim.idle());
+    }
+
+    #[test]
+    fn long_write_is_reloaded() {
+        let sim = SimI2c::new();
+        let res = block_on(write_transfer(&sim, MAGNETO, &[0x55; 300], true), POLLS);
+        assert_eq!(res, Ok(()));
+        assert_eq!(sim.bytes(), 300);
+        assert_eq!(sim.starts(), 1);
+    }
+
+    #[test]
+    fn transaction_merges_operations_of_one_direction() {
+        let sim = SimI2c::new();
+        sim.respond(&[7]);
+        let mut buf = [0; 1];
+        let mut operations = [
+            Operation::Write(&[1]),
+            Operation::Write(&[2, 3]),
+            Operation::Read(&mut buf),
+        ];
+        let res = block_on(transaction_transfer(&sim, ACCEL, &mut operations), POLLS);
+        assert_eq!(res, Ok(()));
+        assert_eq!(sim.starts(), 2);
+        let (written, len) = sim.written();
+        assert_eq!(&written[..len], &[1, 2, 3]);
+        assert_eq!(buf, [7]);
+    }
+
+    #[test]
+    fn ten_bit_address() {
+        let sim = SimI2c::new();
+        let address = I2cAddress::ten_bit(0x2A5);
+        let res = block_on(write_transfer(&sim, address, &[1], true), POLLS);
+        assert_eq!(res, Ok(()));
+        assert_eq!(sim.cr2() & (CR2_ADD10 | CR2_SADD_MASK), CR2_ADD10 | 0x2A5);
+    }
+
+    #[test]
+    fn pec_is_sent_after_the_data() {
+        let sim = SimI2c::new();
+        let lines = Some(sim.lines());
+        let res = block_on(
+            write_transfer(&sim, MAGNETO.with_pec(), &[1, 2], true),
+            POLLS,
+        );
+        assert_eq!(res, Ok(()));
+        assert_eq!(sim.bytes(), 3);
+        assert_eq!(sim.written().1, 2);
+        assert_eq!(sim.cr1() & CR1_PECEN, CR1_PECEN);
+        // the transfer consumed its own STOP, the bus is released
+        assert_eq!(sim.isr() & ISR_STOPF, 0);
+        assert!(sim.idle());
+        assert_eq!(check_idle(lines), Ok(()));
+    }
+
+    #[test]
+    fn pec_ends_only_the_read_of_write_read() {
+        let sim = SimI2c::new();
+        sim.respond(&[0x12, 0x34]);
+        let mut buf = [0; 2];
+        let res = block_on(
+            write_read_transfer(&sim, ACCEL.with_pec(), &[0xA8], &mut buf),
+            POLLS,
+        );
+        assert_eq!(res, Ok(()));
+        assert_eq!(buf, [0x12, 0x34]);
+        assert_eq!(sim.bytes(), 4);
+        assert!(sim.idle());
+    }
+
+    #[test]
+    fn pec_mismatch() {
+        let sim = SimI2c::new();
+        sim.respond(&[0x12, 0x34]);
+        sim.pec_mismatch();
+        let mut buf = [0; 2];
+        let res = block_on(read_transfer(&sim, ACCEL.with_pec(), &mut buf), POLLS);
+        assert_eq!(res, Err(I2cError::Pec));
+        assert_eq!(sim.isr() & (ISR_PECERR | ISR_STOPF | ISR_RXNE), 0);
+    }
+
+    #[test]
+    fn pec_is_disabled_for_devices_without_pec() {
+        let sim = SimI2c::new();
+        let res = block_on(write_transfer(&sim, MAGNETO.with_pec(), &[1], true), POLLS);
+        assert_eq!(res, Ok(()));
+        assert_ne!(sim.cr1() & CR1_PECEN, 0);
+        let res = block_on(write_transfer(&sim, ACCEL, &[2], true), POLLS);
+        assert_eq!(res, Ok(()));
+        assert_eq!(sim.cr1() & CR1_PECEN, 0);
+    }
+
+    #[test]
+    fn clock_timeout_is_enabled_after_timeouta() {
+        let sim = SimI2c::new();
+        set_clock_timeout(&sim, Some(97));
+        assert_eq!(sim.timeoutr(), 97 | TIMEOUTR_TIMOUTEN);
+        set_clock_timeout(&sim, None);
+        assert_eq!(sim.timeoutr(), 0);
+    }
+
+    #[test]
+    fn operations_of_one_direction_form_one_transfer() {
+        let (mut a, mut b) = ([0; 2], [0; 3]);
+        let operations = [
+            Operation::Write(&[1]),
+            Operation::Write(&[2, 3]),
+            Operation::Read(&mut a),
+            Operation::Read(&mut b),
+            Operation::Write(&[]),
+        ];
+        assert_eq!(next_run(&operations, 0), (false, 2, 3));
+        assert_eq!(next_run(&operations, 2), (true, 4, 5));
+        assert_eq!(next_run(&operations, 4), (false, 5, 0));
+    }
+
+    #[test]
+    fn clock_timeout_is_not_shorter_than_requested() {
+        // SMBus: 25ms at 8MHz HSI, (97 + 1) * 2048 / 8MHz = 25.09ms
+        assert_eq!(timeout_a(25, 8_000_000), 97);
+        assert_eq!(timeout_a(0, 8_000_000), 0);
+        assert_eq!(timeout_a(1000, 72_000_000), TIMEOUTR_TIMEOUTA_MAX);
     }
 }
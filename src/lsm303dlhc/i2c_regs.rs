@@ -0,0 +1,95 @@
+use core::task::Waker;
+use stm32f3xx_hal::pac::i2c1;
+
+/// I2C_CR1: PE, NOSTRETCH and PECEN
+pub const CR1_PE: u32 = 1 << 0;
+pub const CR1_NOSTRETCH: u32 = 1 << 17;
+pub const CR1_PECEN: u32 = 1 << 23;
+/// I2C_CR2: SADD[9:0], RD_WRN, ADD10, START, NBYTES[7:0], RELOAD, AUTOEND and PECBYTE
+pub const CR2_SADD_MASK: u32 = 0x3FF;
+pub const CR2_RD_WRN: u32 = 1 << 10;
+pub const CR2_ADD10: u32 = 1 << 11;
+pub const CR2_START: u32 = 1 << 13;
+pub const CR2_NBYTES_SHIFT: u32 = 16;
+pub const CR2_NBYTES_MASK: u32 = 0xFF << CR2_NBYTES_SHIFT;
+pub const CR2_RELOAD: u32 = 1 << 24;
+pub const CR2_AUTOEND: u32 = 1 << 25;
+pub const CR2_PECBYTE: u32 = 1 << 26;
+/// I2C_OAR1: OA1[7:1] of a 7 bit own address and OA1EN
+pub const OAR1_OA1_SHIFT: u32 = 1;
+pub const OAR1_OA1EN: u32 = 1 << 15;
+/// I2C_ISR flags. The clear flags in I2C_ICR are at the same positions.
+pub const ISR_TXE: u32 = 1 << 0;
+pub const ISR_TXIS: u32 = 1 << 1;
+pub const ISR_RXNE: u32 = 1 << 2;
+pub const ISR_ADDR: u32 = 1 << 3;
+pub const ISR_NACKF: u32 = 1 << 4;
+pub const ISR_STOPF: u32 = 1 << 5;
+pub const ISR_TC: u32 = 1 << 6;
+pub const ISR_TCR: u32 = 1 << 7;
+pub const ISR_BERR: u32 = 1 << 8;
+pub const ISR_ARLO: u32 = 1 << 9;
+pub const ISR_PECERR: u32 = 1 << 11;
+pub const ISR_TIMEOUT: u32 = 1 << 12;
+/// Transfer direction after an address match: set if the master reads
+pub const ISR_DIR: u32 = 1 << 16;
+
+/// Register access of the I2C master state machine in [super::i2c_no_irq] and the target in [super::i2c_target]
+///
+/// Implemented for the register block of the I2C peripherals, by [super::i2c_irq::I2cIrq] to sleep on interrupts and by
+/// [super::sim_i2c::SimI2c] for host tests.
+pub trait I2cRegisters {
+    fn isr(&self) -> u32;
+    /// Sets TXE in ISR, which flushes TXDR
+    fn flush_txdr(&self);
+    /// Clears the ISR flags set in `bits` through ICR
+    fn clear(&self, bits: u32);
+    fn cr1(&self) -> u32;
+    fn write_cr1(&self, bits: u32);
+    fn cr2(&self) -> u32;
+    fn write_cr2(&self, bits: u32);
+    fn write_timeoutr(&self, bits: u32);
+    fn write_oar1(&self, bits: u32);
+    fn write_txdr(&self, byte: u8);
+    fn read_rxdr(&self) -> u8;
+    /// Wakes `waker` once one of the ISR flags in `events` is set, called before a transfer returns `Pending`
+    ///
+    /// Does nothing by default: the polled drivers are woken by the wake-all task.
+    fn listen(&self, _events: u32, _waker: &Waker) {}
+}
+
+impl I2cRegisters for i2c1::RegisterBlock {
+    fn isr(&self) -> u32 {
+        self.isr.read().bits()
+    }
+    fn flush_txdr(&self) {
+        self.isr.write(|w| w.txe().set_bit());
+    }
+    fn clear(&self, bits: u32) {
+        self.icr.write(|w| unsafe { w.bits(bits) });
+    }
+    fn cr1(&self) -> u32 {
+        self.cr1.read().bits()
+    }
+    fn write_cr1(&self, bits: u32) {
+        self.cr1.write(|w| unsafe { w.bits(bits) });
+    }
+    fn cr2(&self) -> u32 {
+        self.cr2.read().bits()
+    }
+    fn write_cr2(&self, bits: u32) {
+        self.cr2.write(|w| unsafe { w.bits(bits) });
+    }
+    fn write_timeoutr(&self, bits: u32) {
+        self.timeoutr.write(|w| unsafe { w.bits(bits) });
+    }
+    fn write_oar1(&self, bits: u32) {
+        self.oar1.write(|w| unsafe { w.bits(bits) });
+    }
+    fn write_txdr(&self, byte: u8) {
+        self.txdr.write(|w| w.txdata().bits(byte));
+    }
+    fn read_rxdr(&self) -> u8 {
+        self.rxdr.read().rxdata().bits()
+    }
+}
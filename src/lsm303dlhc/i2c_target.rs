@@ -0,0 +1,395 @@
+use super::i2c_no_irq::{finish, set_clock_timeout, software_reset, timeout_a};
+use super::i2c_regs::{
+    I2cRegisters, CR1_NOSTRETCH, CR1_PE, ISR_ADDR, ISR_BERR, ISR_DIR, ISR_NACKF, ISR_RXNE,
+    ISR_STOPF, ISR_TIMEOUT, ISR_TXE, ISR_TXIS, OAR1_OA1EN, OAR1_OA1_SHIFT,
+};
+use super::scanner::{FIRST_ADDRESS, LAST_ADDRESS};
+use super::I2cError;
+use crate::deadline::before;
+use core::future::Future;
+use lilos::time::TickTime;
+use stm32f3xx_hal::i2c::{I2c, Instance, SclPin, SdaPin};
+use stm32f3xx_hal::pac::i2c1;
+use stm32f3xx_hal::time::duration::Milliseconds;
+use stm32f3xx_hal::time::rate::Hertz;
+
+/// Register bytes exchanged with the [RegisterMap] at once
+const CHUNK: usize = 16;
+
+/// Registers served by an [I2cTarget], e.g. fused orientation, raw sensor data and configuration as in
+/// [crate::sensor_registers::SensorRegisters]
+///
+/// The host writes the register address as first byte of a transfer, followed by the data for the registers or by a
+/// repeated START to read them. The register address increments with every byte, so the host can read all axes at
+/// once. SCL is stretched while a callback runs, so they may await the sensors or other tasks.
+pub trait RegisterMap {
+    /// The host wrote `data` to the registers from `register` on
+    fn write<'a>(&'a mut self, register: u8, data: &'a [u8]) -> impl Future<Output = ()> + 'a;
+    /// Fills `buf` with the registers from `register` on. The bytes beyond the end of the host read are dropped.
+    fn read<'a>(&'a mut self, register: u8, buf: &'a mut [u8]) -> impl Future<Output = ()> + 'a;
+    /// The host starts to read from `register` on, called once before the [RegisterMap::read] calls of a read
+    ///
+    /// A long read is split into several [RegisterMap::read] calls, take a snapshot here to keep the values of multi
+    /// byte registers consistent.
+    fn begin_read(&mut self, _register: u8) -> impl Future<Output = ()> + '_ {
+        core::future::ready(())
+    }
+}
+
+/// Own 7 bit address of an [I2cTarget]
+///
+/// The reserved addresses below [FIRST_ADDRESS] and above [LAST_ADDRESS] are rejected, a target there would answer
+/// general calls or the first byte of 10 bit addresses.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct TargetAddress(u8);
+
+impl TargetAddress {
+    /// None if `address` is reserved or no 7 bit address
+    pub const fn new(address: u8) -> Option<Self> {
+        if address >= FIRST_ADDRESS && address <= LAST_ADDRESS {
+            Some(Self(address))
+        } else {
+            None
+        }
+    }
+    pub const fn address(&self) -> u8 {
+        self.0
+    }
+}
+
+/// I2C target (slave) serving a [RegisterMap] at a 7 bit own address on I2C1 or I2C2
+///
+/// Like [super::i2c_no_irq::I2cNoIrq] the peripheral is polled from the task: after an address match and while RXDR
+/// is full or TXDR is empty the peripheral stretches SCL until the task catches up.
+pub struct I2cTarget<T: Instance, SCL, SDA> {
+    i2c: I2c<T, (SCL, SDA)>,
+    /// Register address of the next byte, kept between transfers
+    register: u8,
+}
+
+impl<T: Instance, SCL, SDA> I2cTarget<T, SCL, SDA> {
+    /// Answers to `address` with the timing and clock setup of the HAL I2c
+    pub fn new(i2c: I2c<T, (SCL, SDA)>, address: TargetAddress) -> Self
+    where
+        SCL: SclPin<T>,
+        SDA: SdaPin<T>,
+    {
+        let address = address.address();
+        let mut target = Self { i2c, register: 0 };
+        let regs = target.regs();
+        regs.write_cr1(regs.cr1() & !CR1_PE);
+        // OA1 must only be changed while OA1EN is cleared
+        regs.write_oar1(0);
+        regs.write_oar1(((address as u32) << OAR1_OA1_SHIFT) | OAR1_OA1EN);
+        regs.write_cr1((regs.cr1() & !CR1_NOSTRETCH) | CR1_PE);
+        target
+    }
+    pub fn release(mut self) -> I2c<T, (SCL, SDA)> {
+        self.regs().write_oar1(0);
+        self.i2c
+    }
+    /// Fails a transfer with [I2cError::ClockTimeout] if SCL stays low longer than `timeout`
+    ///
+    /// This includes the clock stretching while a [RegisterMap] callback runs. See
+    /// [super::i2c_no_irq::I2cNoIrq::enable_clock_timeout] for `i2cclk` and the rounding.
+    pub fn enable_clock_timeout(&mut self, timeout: Milliseconds, i2cclk: Hertz) {
+        set_clock_timeout(self.regs(), Some(timeout_a(timeout.0, i2cclk.0)));
+    }
+    pub fn disable_clock_timeout(&mut self) {
+        set_clock_timeout(self.regs(), None);
+    }
+    /// Serves one transfer of the host, from the address match to the STOP
+    ///
+    /// A repeated START continues the transfer, e.g. to read after writing the register address. Fails with
+    /// [I2cError::Timeout] if no transfer ended before `deadline`, which includes the wait for the address match. The
+    /// peripheral is reset after a timeout, a clock timeout or when the future is dropped, so a stalled host does not
+    /// keep SCL stretched. Call this again to wait for the next transfer.
+    pub async fn serve<M: RegisterMap>(
+        &mut self,
+        map: &mut M,
+        deadline: Option<TickTime>,
+    ) -> Result<(), I2cError> {
+        let regs: &i2c1::RegisterBlock = unsafe { self.i2c.peripheral() };
+        let reset = ResetOnDrop(regs);
+        let res = before(deadline, serve_transfer(regs, map, &mut self.register)).await;
+        core::mem::forget(reset);
+        finish(regs, res)
+    }
+    fn regs(&mut self) -> &i2c1::RegisterBlock {
+        unsafe { self.i2c.peripheral() }
+    }
+}
+
+/// Resets the peripheral when an unfinished [I2cTarget::serve] is dropped, which releases a stretched SCL
+struct ResetOnDrop<'a, R: I2cRegisters>(&'a R);
+
+impl<'a, R: I2cRegisters> Drop for ResetOnDrop<'a, R> {
+    fn drop(&mut self) {
+        software_reset(self.0);
+    }
+}
+
+/// Waits until one of the ISR flags in `mask` is set and returns ISR, fails on bus errors and clock timeouts
+struct TargetEvent<'a, R: I2cRegisters> {
+    regs: &'a R,
+    mask: u32,
+}
+impl<'a, R: I2cRegisters> Future for TargetEvent<'a, R> {
+    type Output = Result<u32, I2cError>;
+
+    fn poll(
+        self: core::pin::Pin<&mut Self>,
+        _cx: &mut core::task::Context<'_>,
+    ) -> core::task::Poll<Self::Output> {
+        let isr = self.regs.isr();
+        if isr & ISR_TIMEOUT != 0 {
+            self.regs.clear(ISR_TIMEOUT);
+            core::task::Poll::Ready(Err(I2cError::ClockTimeout))
+        } else if isr & ISR_BERR != 0 {
+            self.regs.clear(ISR_BERR);
+            core::task::Poll::Ready(Err(I2cError::Bus))
+        } else if isr & self.mask != 0 {
+            core::task::Poll::Ready(Ok(isr))
+        } else {
+            core::task::Poll::Pending
+        }
+    }
+}
+
+/// Waits for the address match and serves the transfer, `register` is the register address of the next byte
+pub(super) async fn serve_transfer<R: I2cRegisters, M: RegisterMap>(
+    regs: &R,
+    map: &mut M,
+    register: &mut u8,
+) -> Result<(), I2cError> {
+    let mut isr = TargetEvent {
+        regs,
+        mask: ISR_ADDR,
+    }
+    .await?;
+    loop {
+        isr = if isr & ISR_DIR == 0 {
+            receive(regs, map, register).await?
+        } else {
+            transmit(regs, map, register).await?
+        };
+        if isr & ISR_STOPF != 0 {
+            regs.clear(ISR_STOPF);
+            return Ok(());
+        }
+        // ADDR again: repeated START
+    }
+}
+
+/// Receives a host write after the address match, returns the ISR with STOPF or the ADDR of a repeated START
+///
+/// The first byte is the register address, the data goes to the map in chunks.
+async fn receive<R: I2cRegisters, M: RegisterMap>(
+    regs: &R,
+    map: &mut M,
+    register: &mut u8,
+) -> Result<u32, I2cError> {
+    regs.clear(ISR_ADDR);
+    let mut buf = [0; CHUNK];
+    let mut len = 0;
+    let mut first = true;
+    loop {
+        let isr = TargetEvent {
+            regs,
+            mask: ISR_RXNE | ISR_STOPF | ISR_ADDR,
+        }
+        .await?;
+        if isr & ISR_RXNE != 0 {
+            let byte = regs.read_rxdr();
+            if first {
+                *register = byte;
+                first = false;
+                continue;
+            }
+            buf[len] = byte;
+            len += 1;
+            if len < CHUNK {
+                continue;
+            }
+        }
+        // the next byte or the repeated START waits while the map is written
+        if len > 0 {
+            map.write(*register, &buf[..len]).await;
+            *register = register.wrapping_add(len as u8);
+            len = 0;
+        }
+        if isr & ISR_RXNE == 0 {
+            return Ok(isr);
+        }
+    }
+}
+
+/// Sends the registers to the host after the address match, returns the ISR with STOPF or the ADDR of a repeated
+/// START
+async fn transmit<R: I2cRegisters, M: RegisterMap>(
+    regs: &R,
+    map: &mut M,
+    register: &mut u8,
+) -> Result<u32, I2cError> {
+    let mut buf = [0; CHUNK];
+    // the clock is stretched until ADDR is cleared
+    map.begin_read(*register).await;
+    map.read(*register, &mut buf).await;
+    let mut pos = 0;
+    // TXDR may still hold a byte the last host did not read
+    regs.flush_txdr();
+    regs.clear(ISR_ADDR);
+    loop {
+        let isr = TargetEvent {
+            regs,
+            mask: ISR_TXIS | ISR_NACKF | ISR_STOPF | ISR_ADDR,
+        }
+        .await?;
+        if isr & ISR_NACKF != 0 {
+            // the host ends its read with a NACK, the byte already in TXDR is not sent
+            if isr & ISR_TXE == 0 {
+                *register = register.wrapping_sub(1);
+            }
+            regs.clear(ISR_NACKF);
+        } else if isr & ISR_TXIS != 0 {
+            if pos == CHUNK {
+                map.read(*register, &mut buf).await;
+                pos = 0;
+            }
+            regs.write_txdr(buf[pos]);
+            pos += 1;
+            *register = register.wrapping_add(1);
+        } else {
+            return Ok(isr);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::sim_i2c::{block_on, SimI2c};
+    use super::*;
+
+    const POLLS: usize = 10_000;
+    const OWN_ADDRESS: u8 = 0x2A;
+
+    #[test]
+    fn reserved_addresses_are_rejected() {
+        assert_eq!(
+            TargetAddress::new(OWN_ADDRESS).map(|a| a.address()),
+            Some(OWN_ADDRESS)
+        );
+        assert_eq!(
+            TargetAddress::new(FIRST_ADDRESS).map(|a| a.address()),
+            Some(0x08)
+        );
+        assert_eq!(
+            TargetAddress::new(LAST_ADDRESS).map(|a| a.address()),
+            Some(0x77)
+        );
+        assert_eq!(TargetAddress::new(0x00), None);
+        assert_eq!(TargetAddress::new(0x07), None);
+        assert_eq!(TargetAddress::new(0x78), None);
+        assert_eq!(TargetAddress::new(0x80 | OWN_ADDRESS), None);
+    }
+
+    /// Register memory which counts the callbacks
+    struct Memory {
+        mem: [u8; 256],
+        reads: usize,
+        writes: usize,
+        snapshots: usize,
+    }
+    impl RegisterMap for Memory {
+        async fn write(&mut self, register: u8, data: &[u8]) {
+            for (i, byte) in data.iter().enumerate() {
+                self.mem[register.wrapping_add(i as u8) as usize] = *byte;
+            }
+            self.writes += 1;
+        }
+        async fn read(&mut self, register: u8, buf: &mut [u8]) {
+            for (i, byte) in buf.iter_mut().enumerate() {
+                *byte = self.mem[register.wrapping_add(i as u8) as usize];
+            }
+            self.reads += 1;
+        }
+        async fn begin_read(&mut self, _register: u8) {
+            self.snapshots += 1;
+        }
+    }
+
+    fn setup() -> (SimI2c, Memory) {
+        let sim = SimI2c::new();
+        sim.write_oar1(((OWN_ADDRESS as u32) << OAR1_OA1_SHIFT) | OAR1_OA1EN);
+        let mut memory = Memory {
+            mem: [0; 256],
+            reads: 0,
+            writes: 0,
+            snapshots: 0,
+        };
+        for (i, byte) in memory.mem.iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+        (sim, memory)
+    }
+
+    #[test]
+    fn host_writes_registers() {
+        let (sim, mut memory) = setup();
+        sim.host_transfer(OWN_ADDRESS, &[0x10, 0xA1, 0xA2], 0);
+        let mut register = 0;
+        let res = block_on(serve_transfer(&sim, &mut memory, &mut register), POLLS);
+        assert_eq!(res, Ok(()));
+        assert_eq!(&memory.mem[0x0F..0x13], &[0x0F, 0xA1, 0xA2, 0x12]);
+        assert_eq!(register, 0x12);
+        assert!(sim.idle());
+    }
+
+    #[test]
+    fn host_reads_after_repeated_start() {
+        let (sim, mut memory) = setup();
+        sim.host_transfer(OWN_ADDRESS, &[0x28], 6);
+        let mut register = 0;
+        let res = block_on(serve_transfer(&sim, &mut memory, &mut register), POLLS);
+        assert_eq!(res, Ok(()));
+        let (written, len) = sim.written();
+        assert_eq!(&written[..len], &[0x28, 0x29, 0x2A, 0x2B, 0x2C, 0x2D]);
+        assert_eq!(sim.starts(), 2);
+        // the byte prepared for a 7th read is not counted
+        assert_eq!(register, 0x2E);
+        assert_eq!(memory.writes, 0);
+    }
+
+    #[test]
+    fn register_address_is_kept_between_transfers() {
+        let (sim, mut memory) = setup();
+        let mut register = 0;
+        sim.host_transfer(OWN_ADDRESS, &[0x40], 0);
+        let res = block_on(serve_transfer(&sim, &mut memory, &mut register), POLLS);
+        assert_eq!(res, Ok(()));
+        sim.host_transfer(OWN_ADDRESS, &[], 2);
+        let res = block_on(serve_transfer(&sim, &mut memory, &mut register), POLLS);
+        assert_eq!(res, Ok(()));
+        let (written, len) = sim.written();
+        assert_eq!(&written[..len], &[0x40, 0x41]);
+    }
+
+    #[test]
+    fn long_transfers_are_split_into_chunks() {
+        let (sim, mut memory) = setup();
+        let mut write = [0x55; CHUNK + 2];
+        write[0] = 0x80;
+        sim.host_transfer(OWN_ADDRESS, &write, CHUNK + 1);
+        let mut register = 0;
+        let res = block_on(serve_transfer(&sim, &mut memory, &mut register), POLLS);
+        assert_eq!(res, Ok(()));
+        assert_eq!(memory.writes, 2);
+        assert_eq!(memory.reads, 2);
+        assert_eq!(memory.snapshots, 1);
+        assert!(memory.mem[0x80..0x80 + CHUNK + 1]
+            .iter()
+            .all(|b| *b == 0x55));
+        // the read starts behind the written registers
+        assert_eq!(sim.written().0[0], 0x80 + CHUNK as u8 + 1);
+    }
+}
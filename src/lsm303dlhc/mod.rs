@@ -1,42 +1,195 @@
+/// Async I2C on I2C1 with the data moved by DMA1 channel 6 and 7
+pub mod dma_i2c;
+/// [embedded_hal_async::i2c::I2c] for the async I2C drivers
+pub mod hal_async;
+/// Async I2C driven by the I2C1 event and error interrupts
+pub mod i2c_irq;
 /// Async I2C Implementation: We use the clock strechting feature to avoid the use of interrupts
 ///
 /// While the RX Data Register is not empty, the STM32 I2c Peripheral stretches the SCL Line low and does not perform further communication
 /// This enables us to avoid interrupts to empty the data register. We just poll the RX Register from App Context and if we are too slow the clock
 /// is stretched. This comes at the cost of maximum communication speed but enables us to implement async read/writes without the need for interrupts
 pub mod i2c_no_irq;
+/// I2C register access behind a trait, so the transfers can run on a simulated peripheral
+pub mod i2c_regs;
+/// I2C target mode serving a register map to a host, polled like [i2c_no_irq]
+pub mod i2c_target;
+/// Recovery of a bus blocked by a device holding SDA low
+pub mod recovery;
+/// Scanner listing the devices on a bus
+pub mod scanner;
+/// I2C bus shared by several devices
+pub mod shared_i2c;
+/// Simulated I2C peripheral for host tests
+#[cfg(test)]
+pub mod sim_i2c;
 
-use i2c_no_irq::I2cNoIrq;
-use stm32f3xx_hal::i2c::{Error, Instance};
+use core::future::Future;
+use lilos::time::TickTime;
 
-pub const ACCEL_ADDR: u8 = 0b0001_1110;
+pub const ACCEL_ADDR: u8 = 0b0001_1001;
 pub const MAGNETO_ADDR: u8 = 0b0001_1110;
+const CTRL_REG1_A: u8 = 0x20;
+const CTRL_REG4_A: u8 = 0x23;
+const OUT_X_L_A: u8 = 0x28;
+/// MSB of the accelerometer sub-address enables the address auto increment
+const AUTO_INCREMENT_A: u8 = 1 << 7;
 const CRA_REG_M: u8 = 0x00;
 const CRB_REG_M: u8 = 0x01;
 const MR_REG_M: u8 = 0x02;
 
+/// Errors of the async I2C drivers
+///
+/// The first variants match [stm32f3xx_hal::i2c::Error].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum I2cError {
+    Arbitration,
+    Bus,
+    Nack,
+    /// SDA is held low by a device. Use [AsyncI2cBus::recover] to free the bus.
+    BusStuck,
+    /// The deadline passed before the transfer finished. The peripheral was reset.
+    Timeout,
+    /// A device held SCL low longer than the hardware timeout (TIMEOUTR). The peripheral was reset.
+    ClockTimeout,
+    /// The SMBus packet error code received from a device with [I2cAddress::with_pec] did not match the data
+    Pec,
+    /// A buffer is longer than the driver can move in one transfer ([crate::dma::MAX_TRANSFER] bytes with DMA).
+    /// Nothing was sent.
+    TooLong,
+}
+
+/// Address of a device and its addressing mode
+///
+/// A `u8` converts to a 7 bit address. With [Self::with_pec] every transfer ending with STOP carries the SMBus packet
+/// error code, generated by the hardware on writes and checked on reads.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct I2cAddress {
+    address: u16,
+    ten_bit: bool,
+    pec: bool,
+}
+
+impl I2cAddress {
+    pub const fn seven_bit(address: u8) -> Self {
+        Self {
+            address: address as u16 & 0x7F,
+            ten_bit: false,
+            pec: false,
+        }
+    }
+    pub const fn ten_bit(address: u16) -> Self {
+        Self {
+            address: address & 0x3FF,
+            ten_bit: true,
+            pec: false,
+        }
+    }
+    pub const fn with_pec(self) -> Self {
+        Self { pec: true, ..self }
+    }
+    pub const fn address(&self) -> u16 {
+        self.address
+    }
+    pub const fn is_ten_bit(&self) -> bool {
+        self.ten_bit
+    }
+    pub const fn pec(&self) -> bool {
+        self.pec
+    }
+}
+
+impl From<u8> for I2cAddress {
+    fn from(address: u8) -> Self {
+        Self::seven_bit(address)
+    }
+}
+
 #[derive(Debug)]
 pub enum Lsm303Error {
     General,
-    Communication(Error),
+    Communication(I2cError),
 }
-impl From<Error> for Lsm303Error {
-    fn from(value: Error) -> Self {
+impl From<I2cError> for Lsm303Error {
+    fn from(value: I2cError) -> Self {
         Self::Communication(value)
     }
 }
-pub struct Lsm303dlhc<T: Instance, SCL, SDA> {
-    i2c: I2cNoIrq<T, SCL, SDA>,
+
+/// Async I2C master
+///
+/// Implemented by the polling [i2c_no_irq::I2cNoIrq] and the interrupt driven [i2c_irq::I2cIrq]. The device address is
+/// given per call together with its addressing mode, so one bus serves any number of devices (see
+/// [shared_i2c::SharedI2cBus]).
+pub trait AsyncI2cBus {
+    /// Reads `buffer.len()` bytes from the device at `address` and ends the transfer with STOP
+    fn read<'a>(
+        &'a mut self,
+        address: I2cAddress,
+        buffer: &'a mut [u8],
+        deadline: Option<TickTime>,
+    ) -> impl Future<Output = Result<(), I2cError>> + 'a;
+    /// Writes `buffer` to the device at `address`, without `with_end` no STOP is generated
+    fn write<'a>(
+        &'a mut self,
+        address: I2cAddress,
+        buffer: &'a [u8],
+        with_end: bool,
+        deadline: Option<TickTime>,
+    ) -> impl Future<Output = Result<(), I2cError>> + 'a;
+    /// Writes `bytes` to the device at `address` and reads `buffer` after a repeated START
+    fn write_read<'a>(
+        &'a mut self,
+        address: I2cAddress,
+        bytes: &'a [u8],
+        buffer: &'a mut [u8],
+        deadline: Option<TickTime>,
+    ) -> impl Future<Output = Result<(), I2cError>> + 'a;
+    /// Frees the bus after [I2cError::BusStuck] and re-initializes the peripheral
+    fn recover(&mut self) -> impl Future<Output = Result<(), I2cError>> + '_;
+}
+
+/// A single device on an I2C bus as used by [Lsm303dlhc]. Every transfer ends with STOP.
+pub trait AsyncI2cDevice {
+    fn address(&self) -> I2cAddress;
+    fn read<'a>(
+        &'a mut self,
+        buffer: &'a mut [u8],
+        deadline: Option<TickTime>,
+    ) -> impl Future<Output = Result<(), I2cError>> + 'a;
+    fn write<'a>(
+        &'a mut self,
+        bytes: &'a [u8],
+        deadline: Option<TickTime>,
+    ) -> impl Future<Output = Result<(), I2cError>> + 'a;
+    /// Writes `bytes` and reads `buffer` after a repeated START
+    fn write_read<'a>(
+        &'a mut self,
+        bytes: &'a [u8],
+        buffer: &'a mut [u8],
+        deadline: Option<TickTime>,
+    ) -> impl Future<Output = Result<(), I2cError>> + 'a;
+}
+
+/// Magnetometer and accelerometer of the LSM303DLHC, two devices on the same bus
+pub struct Lsm303dlhc<D: AsyncI2cDevice> {
+    accel: D,
+    magneto: D,
 }
 
-impl<T: Instance, SCL, SDA> Lsm303dlhc<T, SCL, SDA> {
-    pub fn new(i2c: I2cNoIrq<T, SCL, SDA>) -> Self {
-        Self { i2c }
+impl<D: AsyncI2cDevice> Lsm303dlhc<D> {
+    /// `accel` must be addressed with [ACCEL_ADDR] and `magneto` with [MAGNETO_ADDR]
+    pub fn new(accel: D, magneto: D) -> Self {
+        Self { accel, magneto }
+    }
+    pub fn release(self) -> (D, D) {
+        (self.accel, self.magneto)
     }
 
     pub async fn get_orientation(&mut self) -> Result<(i16, i16, i16), Lsm303Error> {
-        self.i2c.write(&[0x03], false).await?;
         let mut buf = [0 as u8; 6];
-        self.i2c.read(&mut buf).await?;
+        // OUT_X_H_M..OUT_Y_L_M
+        self.magneto.write_read(&[0x03], &mut buf, None).await?;
         let x = i16::from_be_bytes([buf[0], buf[1]]);
         let z = i16::from_be_bytes([buf[2], buf[3]]);
         let y = i16::from_be_bytes([buf[4], buf[5]]);
@@ -47,10 +200,25 @@ impl<T: Instance, SCL, SDA> Lsm303dlhc<T, SCL, SDA> {
             Ok((x, y, z))
         }
     }
+    /// Reads the acceleration of all axis in mg
+    pub async fn get_acceleration(&mut self) -> Result<(i16, i16, i16), Lsm303Error> {
+        let mut buf = [0 as u8; 6];
+        self.accel
+            .write_read(&[OUT_X_L_A | AUTO_INCREMENT_A], &mut buf, None)
+            .await?;
+        // 12 bit left aligned, 1 mg/LSB at +-2g
+        let x = i16::from_le_bytes([buf[0], buf[1]]) >> 4;
+        let y = i16::from_le_bytes([buf[2], buf[3]]) >> 4;
+        let z = i16::from_le_bytes([buf[4], buf[5]]) >> 4;
+        Ok((x, y, z))
+    }
     pub async fn setup(&mut self) -> Result<(), Lsm303Error> {
-        self.i2c.write(&[CRA_REG_M, 0b1001_1100], true).await?;
-        self.i2c.write(&[CRB_REG_M, 0b0010_0000], true).await?;
-        self.i2c.write(&[MR_REG_M, 0b00], true).await?;
+        self.magneto.write(&[CRA_REG_M, 0b1001_1100], None).await?;
+        self.magneto.write(&[CRB_REG_M, 0b0010_0000], None).await?;
+        self.magneto.write(&[MR_REG_M, 0b00], None).await?;
+        // 100Hz, all axis enabled / +-2g, high resolution
+        self.accel.write(&[CTRL_REG1_A, 0b0101_0111], None).await?;
+        self.accel.write(&[CTRL_REG4_A, 0b0000_1000], None).await?;
         Ok(())
     }
 }
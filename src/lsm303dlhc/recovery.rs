@@ -0,0 +1,203 @@
+use super::i2c_regs::{I2cRegisters, CR1_PE};
+use super::I2cError;
+use cortex_m::asm::delay;
+use lilos::exec::yield_cpu;
+use stm32f3xx_hal::gpio::{Alternate, Gpiob, OpenDrain, Pin, U};
+use stm32f3xx_hal::pac::GPIOB;
+
+/// Half of a 100kHz SCL period at up to 72MHz
+const HALF_PERIOD_CYCLES: u32 = 360;
+/// Clock pulses which let a device finish the byte it is sending (8 data bits + ACK)
+const RECOVERY_PULSES: usize = 9;
+
+/// Raw GPIO access to the SCL and SDA pins of an I2C for the bus recovery
+///
+/// The pins are owned by the HAL I2c, so the recovery drives them by their registers while the peripheral is disabled.
+/// The drivers use them after `with_bus_pins`, other pins only need this trait to get the idle check and recovery.
+pub trait I2cBusPins {
+    fn scl_is_low() -> bool;
+    fn sda_is_low() -> bool;
+    /// Switches both pins to open-drain GPIO outputs (`gpio`) or back to their I2C alternate function
+    fn set_gpio_mode(gpio: bool);
+    fn set_scl(high: bool);
+    fn set_sda(high: bool);
+}
+
+const SCL_PB6: u32 = 6;
+const SDA_PB7: u32 = 7;
+
+/// I2C1 on PB6/PB7 as used by the board
+impl I2cBusPins
+    for (
+        Pin<Gpiob, U<6>, Alternate<OpenDrain, 4>>,
+        Pin<Gpiob, U<7>, Alternate<OpenDrain, 4>>,
+    )
+{
+    fn scl_is_low() -> bool {
+        let gpiob = unsafe { &*GPIOB::ptr() };
+        gpiob.idr.read().bits() & (1 << SCL_PB6) == 0
+    }
+    fn sda_is_low() -> bool {
+        let gpiob = unsafe { &*GPIOB::ptr() };
+        gpiob.idr.read().bits() & (1 << SDA_PB7) == 0
+    }
+    fn set_gpio_mode(gpio: bool) {
+        let gpiob = unsafe { &*GPIOB::ptr() };
+        // MODER: 0b01 output, 0b10 alternate function. OTYPER stays open-drain.
+        let mode = if gpio { 0b01 } else { 0b10 };
+        gpiob.moder.modify(|r, w| unsafe {
+            let bits = r.bits() & !((0b11 << (2 * SCL_PB6)) | (0b11 << (2 * SDA_PB7)));
+            w.bits(bits | (mode << (2 * SCL_PB6)) | (mode << (2 * SDA_PB7)))
+        });
+    }
+    fn set_scl(high: bool) {
+        set_pin(SCL_PB6, high);
+    }
+    fn set_sda(high: bool) {
+        set_pin(SDA_PB7, high);
+    }
+}
+
+fn set_pin(pin: u32, high: bool) {
+    let gpiob = unsafe { &*GPIOB::ptr() };
+    let bit = if high { 1 << pin } else { 1 << (pin + 16) };
+    gpiob.bsrr.write(|w| unsafe { w.bits(bit) });
+}
+
+/// The [I2cBusPins] of a driver, stored as functions so the transfers need no bound on the pin types
+///
+/// Drivers without them skip the idle check before a transfer and only reset the peripheral in `recover`.
+#[derive(Clone, Copy)]
+pub struct BusLines {
+    scl_is_low: fn() -> bool,
+    sda_is_low: fn() -> bool,
+    set_gpio_mode: fn(bool),
+    set_scl: fn(bool),
+    set_sda: fn(bool),
+}
+
+impl BusLines {
+    pub fn of<P: I2cBusPins>() -> Self {
+        Self {
+            scl_is_low: P::scl_is_low,
+            sda_is_low: P::sda_is_low,
+            set_gpio_mode: P::set_gpio_mode,
+            set_scl: P::set_scl,
+            set_sda: P::set_sda,
+        }
+    }
+    /// A STOP is generated before SDA can be held low, so SDA low with SCL high before a transfer means a device hangs
+    pub(super) fn check_idle(&self) -> Result<(), I2cError> {
+        if (self.sda_is_low)() && !(self.scl_is_low)() {
+            Err(I2cError::BusStuck)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Disables the peripheral, frees the bus with [clear_bus] if the driver has `lines` and enables the peripheral again
+///
+/// The pins are switched back to their alternate function and PE is set by a guard, also if the future is dropped
+/// while clocking SCL (e.g. by a deadline).
+pub(super) async fn recover_bus<R: I2cRegisters>(
+    regs: &R,
+    lines: Option<BusLines>,
+) -> Result<(), I2cError> {
+    let _restore = RestoreI2c { regs, lines };
+    regs.write_cr1(regs.cr1() & !CR1_PE);
+    let released = match lines {
+        Some(lines) => clear_bus(lines).await,
+        None => true,
+    };
+    if released {
+        Ok(())
+    } else {
+        Err(I2cError::BusStuck)
+    }
+}
+
+/// Hands the pins back to the peripheral and enables it when dropped
+struct RestoreI2c<'a, R: I2cRegisters> {
+    regs: &'a R,
+    lines: Option<BusLines>,
+}
+
+impl<'a, R: I2cRegisters> Drop for RestoreI2c<'a, R> {
+    fn drop(&mut self) {
+        if let Some(lines) = self.lines {
+            (lines.set_gpio_mode)(false);
+        }
+        self.regs.write_cr1(self.regs.cr1() | CR1_PE);
+    }
+}
+
+/// Clocks SCL until a device holding SDA low releases it and generates a STOP
+///
+/// The I2C peripheral must be disabled. Returns false if SDA is still held low afterwards. Leaves the pins in GPIO
+/// mode, [recover_bus] switches them back.
+async fn clear_bus(lines: BusLines) -> bool {
+    (lines.set_scl)(true);
+    (lines.set_sda)(true);
+    (lines.set_gpio_mode)(true);
+    for _ in 0..RECOVERY_PULSES {
+        if !(lines.sda_is_low)() {
+            break;
+        }
+        (lines.set_scl)(false);
+        delay(HALF_PERIOD_CYCLES);
+        (lines.set_scl)(true);
+        delay(HALF_PERIOD_CYCLES);
+        yield_cpu().await;
+    }
+    // STOP: SDA rises while SCL is high
+    (lines.set_scl)(false);
+    delay(HALF_PERIOD_CYCLES);
+    (lines.set_sda)(false);
+    delay(HALF_PERIOD_CYCLES);
+    (lines.set_scl)(true);
+    delay(HALF_PERIOD_CYCLES);
+    (lines.set_sda)(true);
+    delay(HALF_PERIOD_CYCLES);
+    !(lines.sda_is_low)()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::sim_i2c::{block_on, poll_and_drop, SimI2c};
+    use super::*;
+
+    const POLLS: usize = 100;
+
+    #[test]
+    fn recovery_frees_a_hanging_device() {
+        let sim = SimI2c::new();
+        let lines = sim.lines();
+        sim.hold_sda(3);
+        assert_eq!(lines.check_idle(), Err(I2cError::BusStuck));
+        assert_eq!(block_on(recover_bus(&sim, Some(lines)), POLLS), Ok(()));
+        assert_eq!(lines.check_idle(), Ok(()));
+        assert!(!sim.gpio_mode());
+        assert_eq!(sim.cr1() & CR1_PE, CR1_PE);
+    }
+
+    #[test]
+    fn device_still_hanging_after_the_pulses() {
+        let sim = SimI2c::new();
+        sim.hold_sda(RECOVERY_PULSES + 2);
+        let res = block_on(recover_bus(&sim, Some(sim.lines())), POLLS);
+        assert_eq!(res, Err(I2cError::BusStuck));
+        assert!(!sim.gpio_mode());
+        assert_eq!(sim.cr1() & CR1_PE, CR1_PE);
+    }
+
+    #[test]
+    fn cancelled_recovery_restores_the_pins_and_the_peripheral() {
+        let sim = SimI2c::new();
+        sim.write_cr1(CR1_PE);
+        sim.hold_sda(5);
+        assert!(!poll_and_drop(recover_bus(&sim, Some(sim.lines())), 2));
+        assert!(!sim.gpio_mode());
+        assert_eq!(sim.cr1() & CR1_PE, CR1_PE);
+    }
+}
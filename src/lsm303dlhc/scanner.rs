@@ -0,0 +1,135 @@
+use super::{AsyncI2cBus, I2cError};
+use core::fmt;
+use core::time::Duration;
+use lilos::time::TickTime;
+
+/// 0x00..=0x07 (general call, CBUS, HS mode ...) and 0x78..=0x7F (10 bit addressing) are reserved
+pub const FIRST_ADDRESS: u8 = 0x08;
+pub const LAST_ADDRESS: u8 = 0x77;
+
+/// Addresses which acknowledged during a [scan], bit n stands for the 7 bit address n
+#[derive(Clone, Copy, PartialEq, Default, Debug)]
+pub struct I2cScan {
+    bits: u128,
+}
+
+impl I2cScan {
+    pub fn contains(&self, address: u8) -> bool {
+        address < 128 && self.bits & (1 << address) != 0
+    }
+    /// Records `address`, values above 0x7F are no 7 bit address and ignored like in [Self::contains]
+    pub fn insert(&mut self, address: u8) {
+        if address < 128 {
+            self.bits |= 1 << address;
+        }
+    }
+    pub fn count(&self) -> u32 {
+        self.bits.count_ones()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.bits == 0
+    }
+    /// The acknowledged addresses in ascending order
+    pub fn addresses(&self) -> impl Iterator<Item = u8> + '_ {
+        (0..128).filter(|a| self.contains(*a))
+    }
+}
+
+/// Prints the addresses, e.g. `I2C devices: 0x19 0x1e`
+impl defmt::Format for I2cScan {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(f, "I2C devices:");
+        for address in self.addresses() {
+            defmt::write!(f, " {=u8:#04x}", address);
+        }
+    }
+}
+
+/// Table like `i2cdetect`, e.g. for a shell command. Reserved addresses are left blank.
+impl fmt::Display for I2cScan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "   ")?;
+        for col in 0..16 {
+            write!(f, "  {:x}", col)?;
+        }
+        for address in 0..128u8 {
+            if address % 16 == 0 {
+                write!(f, "\n{:02x}:", address)?;
+            }
+            if !(FIRST_ADDRESS..=LAST_ADDRESS).contains(&address) {
+                write!(f, "   ")?;
+            } else if self.contains(address) {
+                write!(f, " {:02x}", address)?;
+            } else {
+                write!(f, " --")?;
+            }
+        }
+        writeln!(f)
+    }
+}
+
+/// Probes every non reserved 7 bit address with an address-only write
+///
+/// A NACK means there is no device. Other errors abort the scan, a hanging bus with [I2cError::Timeout] after
+/// `probe_timeout`.
+pub async fn scan<B: AsyncI2cBus>(
+    bus: &mut B,
+    probe_timeout: Duration,
+) -> Result<I2cScan, I2cError> {
+    let mut found = I2cScan::default();
+    for address in FIRST_ADDRESS..=LAST_ADDRESS {
+        let deadline = TickTime::now() + probe_timeout;
+        match bus.write(address.into(), &[], true, Some(deadline)).await {
+            Ok(()) => found.insert(address),
+            Err(I2cError::Nack) => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(found)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::sim_i2c::{block_on, SimI2c};
+    use super::*;
+
+    const POLLS: usize = 100_000;
+
+    #[test]
+    fn addresses_are_ascending() {
+        let mut scan = I2cScan::default();
+        scan.insert(0x1E);
+        scan.insert(0x19);
+        scan.insert(0x77);
+        assert_eq!(scan.count(), 3);
+        assert!(scan.contains(0x19));
+        assert!(!scan.contains(0x18));
+        let mut addresses = scan.addresses();
+        assert_eq!(addresses.next(), Some(0x19));
+        assert_eq!(addresses.next(), Some(0x1E));
+        assert_eq!(addresses.next(), Some(0x77));
+        assert_eq!(addresses.next(), None);
+    }
+
+    #[test]
+    fn out_of_range_addresses_are_ignored() {
+        let mut scan = I2cScan::default();
+        scan.insert(0x80 | 0x19);
+        assert!(scan.is_empty());
+        assert!(!scan.contains(0x19));
+        assert!(!scan.contains(0x80 | 0x19));
+    }
+
+    #[test]
+    fn scan_after_a_data_transfer() {
+        let mut sim = SimI2c::new();
+        sim.devices(&[0x19, 0x1E]);
+        let res = block_on(sim.write(0x19.into(), &[0x20, 0x57], true, None), POLLS);
+        assert_eq!(res, Ok(()));
+        let found = block_on(scan(&mut sim, Duration::from_millis(10)), POLLS);
+        let mut expected = I2cScan::default();
+        expected.insert(0x19);
+        expected.insert(0x1E);
+        assert_eq!(found, Ok(expected));
+    }
+}
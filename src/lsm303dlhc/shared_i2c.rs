@@ -0,0 +1,89 @@
+use super::scanner::{scan, I2cScan};
+use super::{AsyncI2cBus, AsyncI2cDevice, I2cAddress, I2cError};
+use crate::deadline::before;
+use crate::mutex::{Mutex, MutexGuard};
+use core::future::Future;
+use core::time::Duration;
+use lilos::time::TickTime;
+
+/// I2C bus shared by several devices
+///
+/// Every device gets its own handle carrying its address. Each transfer locks the bus, so the handles may be used from
+/// different tasks.
+pub struct SharedI2cBus<B> {
+    bus: Mutex<B>,
+}
+
+impl<B: AsyncI2cBus> SharedI2cBus<B> {
+    pub fn new(bus: B) -> Self {
+        Self {
+            bus: Mutex::new(bus),
+        }
+    }
+    /// Handle of the device at `address`, a `u8` is a 7 bit address
+    pub fn device(&self, address: impl Into<I2cAddress>) -> SharedI2cDevice<'_, B> {
+        SharedI2cDevice {
+            bus: self,
+            address: address.into(),
+        }
+    }
+    /// Waits for the bus and runs [AsyncI2cBus::recover]
+    pub async fn recover(&self) -> Result<(), I2cError> {
+        self.bus.lock().await.recover().await
+    }
+    /// Waits for the bus and runs [scan]
+    pub async fn scan(&self, probe_timeout: Duration) -> Result<I2cScan, I2cError> {
+        scan(&mut *self.bus.lock().await, probe_timeout).await
+    }
+    /// The deadline also limits the wait for the other devices
+    async fn lock(&self, deadline: Option<TickTime>) -> Result<MutexGuard<'_, B>, I2cError> {
+        before(deadline, self.bus.lock())
+            .await
+            .ok_or(I2cError::Timeout)
+    }
+    pub fn release(self) -> B {
+        self.bus.into_inner()
+    }
+}
+
+pub struct SharedI2cDevice<'a, B> {
+    bus: &'a SharedI2cBus<B>,
+    address: I2cAddress,
+}
+
+impl<'a, B: AsyncI2cBus> AsyncI2cDevice for SharedI2cDevice<'a, B> {
+    fn address(&self) -> I2cAddress {
+        self.address
+    }
+    fn read<'b>(
+        &'b mut self,
+        buffer: &'b mut [u8],
+        deadline: Option<TickTime>,
+    ) -> impl Future<Output = Result<(), I2cError>> + 'b {
+        async move {
+            let mut bus = self.bus.lock(deadline).await?;
+            bus.read(self.address, buffer, deadline).await
+        }
+    }
+    fn write<'b>(
+        &'b mut self,
+        bytes: &'b [u8],
+        deadline: Option<TickTime>,
+    ) -> impl Future<Output = Result<(), I2cError>> + 'b {
+        async move {
+            let mut bus = self.bus.lock(deadline).await?;
+            bus.write(self.address, bytes, true, deadline).await
+        }
+    }
+    fn write_read<'b>(
+        &'b mut self,
+        bytes: &'b [u8],
+        buffer: &'b mut [u8],
+        deadline: Option<TickTime>,
+    ) -> impl Future<Output = Result<(), I2cError>> + 'b {
+        async move {
+            let mut bus = self.bus.lock(deadline).await?;
+            bus.write_read(self.address, bytes, buffer, deadline).await
+        }
+    }
+}
@@ -0,0 +1,596 @@
+extern crate std;
+
+use super::i2c_no_irq::{check_idle, read_transfer, write_read_transfer, write_transfer};
+use super::i2c_regs::*;
+use super::recovery::{BusLines, I2cBusPins};
+use super::{AsyncI2cBus, I2cAddress, I2cError};
+use core::cell::RefCell;
+use core::future::Future;
+use core::pin::pin;
+use core::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+use lilos::time::TickTime;
+use std::rc::Rc;
+
+/// Data bytes recorded and answered by the simulated target
+const SIM_BUF: usize = 64;
+/// ISR reads from the end of a transfer (last byte or NACK) to the STOP the master generates after it
+const STOP_DELAY: usize = 3;
+
+/// Reaction of the target or the bus at a scripted data byte
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SimFault {
+    /// The target does not acknowledge the byte, the master generates STOP
+    Nack,
+    /// Another master wins the arbitration
+    Arbitration,
+    /// Misplaced START or STOP
+    Bus,
+    /// SCL held low longer than the TIMEOUTR limit
+    ClockTimeout,
+}
+
+/// Transfer of a simulated host to the peripheral in target mode
+#[derive(Clone, Copy)]
+struct Host {
+    address: u8,
+    write: [u8; SIM_BUF],
+    write_len: usize,
+    write_pos: usize,
+    read: usize,
+    sent: usize,
+    phase: HostPhase,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum HostPhase {
+    Start,
+    Write,
+    Read,
+}
+
+struct State {
+    cr1: u32,
+    cr2: u32,
+    isr: u32,
+    timeoutr: u32,
+    oar1: u32,
+    rxdr: u8,
+    txdr: u8,
+    /// START requested, the address is sent at the next ISR read
+    start: bool,
+    /// Address acknowledged and no STOP or error since
+    active: bool,
+    /// ISR reads until the STOP after the last byte or a NACK
+    stop_in: usize,
+    /// ISR flags of the last [I2cRegisters::listen]
+    listened: u32,
+    /// SCL pulses until a hanging device releases SDA
+    sda_held: usize,
+    /// The pins are GPIOs of the bus recovery
+    gpio: bool,
+    /// Data bytes left in the current NBYTES chunk
+    left: usize,
+    /// Data bytes on the bus over all transfers, both directions
+    bytes: usize,
+    starts: usize,
+    nack_address: bool,
+    /// 7 bit addresses which acknowledge, without every address does
+    devices: Option<u128>,
+    pec_mismatch: bool,
+    fault: Option<(usize, SimFault)>,
+    written: [u8; SIM_BUF],
+    written_len: usize,
+    response: [u8; SIM_BUF],
+    response_pos: usize,
+    host: Option<Host>,
+}
+
+/// Simulated I2C peripheral in master mode with one target, or in target mode with one host, for host tests
+///
+/// The hardware advances whenever the driver reads ISR, TXDR or RXDR: START sends the address, an empty TXDR raises
+/// TXIS, the next response byte raises RXNE and the end of a chunk raises TCR, TC or STOPF. A NACK of the address and
+/// errors at a given data byte can be scripted. The STOP after the last byte or a NACK follows a few ISR reads later,
+/// until then [Self::lines] report the bus as busy.
+///
+/// In target mode a transfer of the host is scripted with [Self::host_transfer]. A matching address raises ADDR and
+/// the peripheral stretches the clock until the driver clears it, TXIS requests the next byte one byte ahead like the
+/// hardware and the host ends its read with NACK and STOP.
+pub struct SimI2c {
+    state: Rc<RefCell<State>>,
+}
+
+std::thread_local! {
+    /// State of the [SimI2c] whose [SimI2c::lines] were taken last on this thread
+    static LINES: RefCell<Option<Rc<RefCell<State>>>> = const { RefCell::new(None) };
+}
+
+/// [I2cBusPins] of the [SimI2c] in [LINES]: SDA is low while a transfer is on the bus or a device hangs
+struct SimPins;
+
+impl SimPins {
+    fn with<T: Default>(f: impl FnOnce(&mut State) -> T) -> T {
+        LINES.with(|lines| {
+            lines
+                .borrow()
+                .as_ref()
+                .map_or_else(T::default, |s| f(&mut s.borrow_mut()))
+        })
+    }
+}
+
+impl I2cBusPins for SimPins {
+    fn scl_is_low() -> bool {
+        false
+    }
+    fn sda_is_low() -> bool {
+        Self::with(|s| s.busy() || s.sda_held > 0)
+    }
+    fn set_gpio_mode(gpio: bool) {
+        Self::with(|s| s.gpio = gpio)
+    }
+    /// A rising edge in GPIO mode clocks the hanging device
+    fn set_scl(high: bool) {
+        Self::with(|s| {
+            if high && s.gpio {
+                s.sda_held = s.sda_held.saturating_sub(1);
+            }
+        })
+    }
+    fn set_sda(_high: bool) {}
+}
+
+impl SimI2c {
+    pub fn new() -> Self {
+        Self {
+            state: Rc::new(RefCell::new(State {
+                cr1: CR1_PE,
+                cr2: 0,
+                isr: 0,
+                timeoutr: 0,
+                oar1: 0,
+                rxdr: 0,
+                txdr: 0,
+                start: false,
+                active: false,
+                stop_in: 0,
+                listened: 0,
+                sda_held: 0,
+                gpio: false,
+                left: 0,
+                bytes: 0,
+                starts: 0,
+                nack_address: false,
+                devices: None,
+                pec_mismatch: false,
+                fault: None,
+                written: [0; SIM_BUF],
+                written_len: 0,
+                response: [0; SIM_BUF],
+                response_pos: 0,
+                host: None,
+            })),
+        }
+    }
+    /// SCL and SDA of the bus, e.g. for [super::i2c_no_irq::check_idle]
+    pub fn lines(&self) -> BusLines {
+        LINES.with(|lines| *lines.borrow_mut() = Some(self.state.clone()));
+        BusLines::of::<SimPins>()
+    }
+    /// Bytes the target sends on reads, 0xFF after them
+    pub fn respond(&self, bytes: &[u8]) {
+        let mut s = self.state.borrow_mut();
+        s.response[..bytes.len()].copy_from_slice(bytes);
+        s.response_pos = 0;
+    }
+    /// No target answers the address
+    pub fn nack_address(&self) {
+        self.state.borrow_mut().nack_address = true;
+    }
+    /// Only targets at the 7 bit `addresses` answer, instead of one target at every address
+    pub fn devices(&self, addresses: &[u8]) {
+        let bits = addresses
+            .iter()
+            .fold(0u128, |bits, a| bits | 1 << (a & 0x7F));
+        self.state.borrow_mut().devices = Some(bits);
+    }
+    /// A device holds SDA low until SCL was clocked `pulses` times
+    pub fn hold_sda(&self, pulses: usize) {
+        self.state.borrow_mut().sda_held = pulses;
+    }
+    /// Whether the bus recovery left the pins in GPIO mode
+    pub fn gpio_mode(&self) -> bool {
+        self.state.borrow().gpio
+    }
+    /// The PEC byte received by the master does not match
+    pub fn pec_mismatch(&self) {
+        self.state.borrow_mut().pec_mismatch = true;
+    }
+    /// Injects `fault` at the data byte with index `byte`, counted over all transfers
+    pub fn fault_at(&self, byte: usize, fault: SimFault) {
+        self.state.borrow_mut().fault = Some((byte, fault));
+    }
+    /// A host writes `write` to the target at `address` and reads `read` bytes after a repeated START
+    ///
+    /// Without `write` the host only reads, without `read` it only writes.
+    pub fn host_transfer(&self, address: u8, write: &[u8], read: usize) {
+        let mut host = Host {
+            address,
+            write: [0; SIM_BUF],
+            write_len: write.len(),
+            write_pos: 0,
+            read,
+            sent: 0,
+            phase: HostPhase::Start,
+        };
+        host.write[..write.len()].copy_from_slice(write);
+        self.state.borrow_mut().host = Some(host);
+    }
+    /// The first [SIM_BUF] bytes sent by the master or, in target mode, by the target
+    pub fn written(&self) -> ([u8; SIM_BUF], usize) {
+        let s = self.state.borrow();
+        (s.written, s.written_len.min(SIM_BUF))
+    }
+    /// Data bytes transferred in both directions
+    pub fn bytes(&self) -> usize {
+        self.state.borrow().bytes
+    }
+    /// Number of (repeated) STARTs
+    pub fn starts(&self) -> usize {
+        self.state.borrow().starts
+    }
+    /// Whether a STOP or error ended the last transfer
+    pub fn idle(&self) -> bool {
+        let s = self.state.borrow();
+        !s.active && s.host.is_none()
+    }
+    /// The ISR flags the driver waited for last
+    pub fn listened(&self) -> u32 {
+        self.state.borrow().listened
+    }
+    /// Last value written to TIMEOUTR
+    pub fn timeoutr(&self) -> u32 {
+        self.state.borrow().timeoutr
+    }
+}
+
+impl Default for SimI2c {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl State {
+    fn read(&self) -> bool {
+        self.cr2 & CR2_RD_WRN != 0
+    }
+    /// A START, data or the STOP is on the bus
+    fn busy(&self) -> bool {
+        self.start || self.active || self.stop_in > 0
+    }
+    fn nbytes(&self) -> usize {
+        ((self.cr2 & CR2_NBYTES_MASK) >> CR2_NBYTES_SHIFT) as usize
+    }
+    /// Fault scripted for the next data byte
+    fn take_fault(&mut self) -> Option<SimFault> {
+        match self.fault {
+            Some((byte, fault)) if byte == self.bytes => {
+                self.fault = None;
+                Some(fault)
+            }
+            _ => None,
+        }
+    }
+    fn raise(&mut self, fault: SimFault) {
+        self.active = false;
+        self.isr |= match fault {
+            SimFault::Nack => {
+                self.stop_in = STOP_DELAY;
+                ISR_NACKF
+            }
+            SimFault::Arbitration => ISR_ARLO,
+            SimFault::Bus => ISR_BERR,
+            SimFault::ClockTimeout => ISR_TIMEOUT,
+        };
+    }
+    fn end_of_chunk(&mut self) {
+        if self.cr2 & CR2_RELOAD != 0 {
+            self.isr |= ISR_TCR;
+        } else if self.cr2 & CR2_AUTOEND != 0 {
+            self.stop_in = STOP_DELAY;
+        } else {
+            self.isr |= ISR_TC;
+        }
+    }
+    fn record(&mut self, byte: u8) {
+        if self.written_len < SIM_BUF {
+            self.written[self.written_len] = byte;
+        }
+        self.written_len += 1;
+    }
+    /// Target mode: the host goes on while no ADDR is pending
+    fn step_host(&mut self, mut host: Host) {
+        let own = ((self.oar1 >> OAR1_OA1_SHIFT) & 0x7F) as u8;
+        if self.oar1 & OAR1_OA1EN == 0 || own != host.address || self.isr & ISR_ADDR != 0 {
+            return;
+        }
+        match host.phase {
+            HostPhase::Start if host.write_len > 0 => {
+                self.starts += 1;
+                host.phase = HostPhase::Write;
+                self.isr = (self.isr | ISR_ADDR) & !ISR_DIR;
+            }
+            HostPhase::Start => {
+                self.starts += 1;
+                host.phase = HostPhase::Read;
+                self.isr |= ISR_ADDR | ISR_DIR;
+            }
+            // RXDR full: the clock is stretched
+            HostPhase::Write if self.isr & ISR_RXNE != 0 => {}
+            HostPhase::Write => {
+                if host.write_pos < host.write_len {
+                    self.rxdr = host.write[host.write_pos];
+                    host.write_pos += 1;
+                    self.bytes += 1;
+                    self.isr |= ISR_RXNE;
+                } else if host.read > 0 {
+                    // repeated START
+                    self.starts += 1;
+                    host.phase = HostPhase::Read;
+                    self.isr |= ISR_ADDR | ISR_DIR;
+                } else {
+                    self.isr |= ISR_STOPF;
+                    self.host = None;
+                    return;
+                }
+            }
+            HostPhase::Read => {
+                if self.isr & ISR_TXE != 0 {
+                    self.isr |= ISR_TXIS;
+                } else if host.sent < host.read {
+                    // TXDR moves to the shift register and the next byte is requested right away
+                    let byte = self.txdr;
+                    self.record(byte);
+                    host.sent += 1;
+                    self.bytes += 1;
+                    self.isr |= ISR_TXE | ISR_TXIS;
+                } else {
+                    // the byte in TXDR is not sent anymore
+                    self.isr |= ISR_NACKF | ISR_STOPF;
+                    self.host = None;
+                    return;
+                }
+            }
+        }
+        self.host = Some(host);
+    }
+    /// The next byte is the PEC byte which ends the transfer
+    fn pec_byte(&self) -> bool {
+        self.left == 1 && self.cr2 & (CR2_PECBYTE | CR2_RELOAD) == CR2_PECBYTE
+    }
+    fn step(&mut self) {
+        if self.stop_in > 0 {
+            self.stop_in -= 1;
+            if self.stop_in == 0 {
+                self.active = false;
+                self.isr |= ISR_STOPF;
+            }
+            return;
+        }
+        if let Some(host) = self.host {
+            return self.step_host(host);
+        }
+        if self.start {
+            self.start = false;
+            let address = (self.cr2 & CR2_SADD_MASK) >> 1 & 0x7F;
+            let absent = self.devices.is_some_and(|d| d & (1 << address) == 0);
+            if self.nack_address || absent {
+                self.raise(SimFault::Nack);
+                return;
+            }
+            self.active = true;
+            if self.left == 0 {
+                self.end_of_chunk();
+            }
+        }
+        if !self.active || self.left == 0 {
+            return;
+        }
+        if self.pec_byte() {
+            if self.read() && self.isr & ISR_RXNE == 0 {
+                // the received PEC byte is compared by the hardware and copied to RXDR
+                self.rxdr = 0;
+                self.bytes += 1;
+                self.left = 0;
+                self.isr |= ISR_RXNE;
+                if self.pec_mismatch {
+                    self.isr |= ISR_PECERR;
+                }
+                self.end_of_chunk();
+            } else if !self.read() {
+                // the hardware sends the PEC byte without TXIS
+                self.bytes += 1;
+                self.left = 0;
+                self.end_of_chunk();
+            }
+        } else if self.read() {
+            if self.isr & ISR_RXNE == 0 {
+                if let Some(fault) = self.take_fault() {
+                    self.raise(fault);
+                    return;
+                }
+                self.rxdr = self
+                    .response
+                    .get(self.response_pos)
+                    .copied()
+                    .unwrap_or(0xFF);
+                self.response_pos += 1;
+                self.isr |= ISR_RXNE;
+            }
+        } else {
+            self.isr |= ISR_TXIS;
+        }
+    }
+}
+
+impl I2cRegisters for SimI2c {
+    fn isr(&self) -> u32 {
+        let mut s = self.state.borrow_mut();
+        s.step();
+        s.isr
+    }
+    fn flush_txdr(&self) {
+        self.state.borrow_mut().isr |= ISR_TXE;
+    }
+    fn clear(&self, bits: u32) {
+        self.state.borrow_mut().isr &= !bits;
+    }
+    fn cr1(&self) -> u32 {
+        self.state.borrow().cr1
+    }
+    /// Clearing PE resets the state machine and the flags
+    fn write_cr1(&self, bits: u32) {
+        let mut s = self.state.borrow_mut();
+        s.cr1 = bits;
+        if bits & CR1_PE == 0 {
+            s.isr = 0;
+            s.start = false;
+            s.active = false;
+            s.stop_in = 0;
+        }
+    }
+    fn cr2(&self) -> u32 {
+        self.state.borrow().cr2
+    }
+    fn write_cr2(&self, bits: u32) {
+        let mut s = self.state.borrow_mut();
+        // START is cleared by the hardware once the address is sent
+        s.cr2 = bits & !CR2_START;
+        if bits & CR2_START != 0 {
+            s.starts += 1;
+            s.start = true;
+            s.isr &= !(ISR_TC | ISR_TXIS);
+            s.left = s.nbytes();
+        } else if s.isr & ISR_TCR != 0 {
+            s.isr &= !ISR_TCR;
+            s.left = s.nbytes();
+        }
+    }
+    fn write_timeoutr(&self, bits: u32) {
+        self.state.borrow_mut().timeoutr = bits;
+    }
+    fn write_oar1(&self, bits: u32) {
+        self.state.borrow_mut().oar1 = bits;
+    }
+    fn write_txdr(&self, byte: u8) {
+        let mut s = self.state.borrow_mut();
+        assert!(s.isr & ISR_TXIS != 0, "TXDR written without TXIS");
+        s.isr &= !ISR_TXIS;
+        if s.host.is_some() {
+            s.isr &= !ISR_TXE;
+            s.txdr = byte;
+            return;
+        }
+        s.record(byte);
+        s.left -= 1;
+        let fault = s.take_fault();
+        s.bytes += 1;
+        match fault {
+            Some(fault) => s.raise(fault),
+            None if s.left == 0 => s.end_of_chunk(),
+            None => {}
+        }
+    }
+    fn read_rxdr(&self) -> u8 {
+        let mut s = self.state.borrow_mut();
+        assert!(s.isr & ISR_RXNE != 0, "RXDR read without RXNE");
+        s.isr &= !ISR_RXNE;
+        // target mode or the PEC byte, which was counted on reception
+        if s.host.is_some() || s.left == 0 {
+            return s.rxdr;
+        }
+        s.left -= 1;
+        s.bytes += 1;
+        if s.left == 0 {
+            s.end_of_chunk();
+        }
+        s.rxdr
+    }
+    fn listen(&self, events: u32, _waker: &Waker) {
+        self.state.borrow_mut().listened = events;
+    }
+}
+
+/// The bus with its idle check for bus level users like [super::scanner::scan]
+///
+/// Deadlines are ignored, [block_on] bounds the transfers instead.
+impl AsyncI2cBus for SimI2c {
+    fn read<'a>(
+        &'a mut self,
+        address: I2cAddress,
+        buffer: &'a mut [u8],
+        _deadline: Option<TickTime>,
+    ) -> impl Future<Output = Result<(), I2cError>> + 'a {
+        async move {
+            check_idle(Some(self.lines()))?;
+            read_transfer(self, address, buffer).await
+        }
+    }
+    fn write<'a>(
+        &'a mut self,
+        address: I2cAddress,
+        buffer: &'a [u8],
+        with_end: bool,
+        _deadline: Option<TickTime>,
+    ) -> impl Future<Output = Result<(), I2cError>> + 'a {
+        async move {
+            check_idle(Some(self.lines()))?;
+            write_transfer(self, address, buffer, with_end).await
+        }
+    }
+    fn write_read<'a>(
+        &'a mut self,
+        address: I2cAddress,
+        bytes: &'a [u8],
+        buffer: &'a mut [u8],
+        _deadline: Option<TickTime>,
+    ) -> impl Future<Output = Result<(), I2cError>> + 'a {
+        async move {
+            check_idle(Some(self.lines()))?;
+            write_read_transfer(self, address, bytes, buffer).await
+        }
+    }
+    fn recover(&mut self) -> impl Future<Output = Result<(), I2cError>> + '_ {
+        async { Ok(()) }
+    }
+}
+
+fn noop_waker() -> Waker {
+    fn noop_clone(_: *const ()) -> RawWaker {
+        RawWaker::new(core::ptr::null(), &NOOP_VTABLE)
+    }
+    fn noop(_: *const ()) {}
+    static NOOP_VTABLE: RawWakerVTable = RawWakerVTable::new(noop_clone, noop, noop, noop);
+
+    unsafe { Waker::from_raw(RawWaker::new(core::ptr::null(), &NOOP_VTABLE)) }
+}
+
+/// Polls `future` until it is ready, panics if it does not finish within `max_polls`
+pub fn block_on<F: Future>(future: F, max_polls: usize) -> F::Output {
+    let waker = noop_waker();
+    let mut cx = Context::from_waker(&waker);
+    let mut future = pin!(future);
+    for _ in 0..max_polls {
+        if let Poll::Ready(res) = future.as_mut().poll(&mut cx) {
+            return res;
+        }
+    }
+    panic!("future did not finish within {} polls", max_polls);
+}
+
+/// Polls `future` `polls` times and drops it, e.g. to cancel it like a missed deadline. Returns whether it finished.
+pub fn poll_and_drop<F: Future>(future: F, polls: usize) -> bool {
+    let waker = noop_waker();
+    let mut cx = Context::from_waker(&waker);
+    let mut future = pin!(future);
+    (0..polls).any(|_| future.as_mut().poll(&mut cx).is_ready())
+}
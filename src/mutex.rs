@@ -0,0 +1,91 @@
+use core::cell::UnsafeCell;
+use core::future::Future;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::Poll;
+use lilos::exec::Notify;
+
+/// Async mutex to share a peripheral (e.g. a bus) between tasks
+///
+/// Waiting tasks are woken when the guard is dropped and compete for the lock again. Only use it from tasks, never from an
+/// interrupt handler.
+pub struct Mutex<T> {
+    locked: AtomicBool,
+    released: Notify,
+    value: UnsafeCell<T>,
+}
+
+// The value is only reachable through a guard and there is at most one guard at a time
+unsafe impl<T: Send> Sync for Mutex<T> {}
+
+impl<T> Mutex<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            locked: AtomicBool::new(false),
+            released: Notify::new(),
+            value: UnsafeCell::new(value),
+        }
+    }
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        self.locked
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .ok()
+            .map(|_| MutexGuard { mutex: self })
+    }
+    /// Waits until the mutex is unlocked and locks it
+    pub fn lock(&self) -> impl Future<Output = MutexGuard<'_, T>> {
+        Lock { mutex: self }
+    }
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+}
+
+struct Lock<'a, T> {
+    mutex: &'a Mutex<T>,
+}
+
+impl<'a, T> Future for Lock<'a, T> {
+    type Output = MutexGuard<'a, T>;
+
+    fn poll(
+        self: core::pin::Pin<&mut Self>,
+        cx: &mut core::task::Context<'_>,
+    ) -> core::task::Poll<Self::Output> {
+        if let Some(guard) = self.mutex.try_lock() {
+            return Poll::Ready(guard);
+        }
+        self.mutex.released.subscribe(cx.waker());
+        // it may have been released before we subscribed
+        match self.mutex.try_lock() {
+            Some(guard) => Poll::Ready(guard),
+            None => Poll::Pending,
+        }
+    }
+}
+
+/// Unlocks the [Mutex] when dropped
+pub struct MutexGuard<'a, T> {
+    mutex: &'a Mutex<T>,
+}
+
+impl<'a, T> Deref for MutexGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.value.get() }
+    }
+}
+
+impl<'a, T> DerefMut for MutexGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.value.get() }
+    }
+}
+
+impl<'a, T> Drop for MutexGuard<'a, T> {
+    fn drop(&mut self) {
+        self.mutex.locked.store(false, Ordering::Release);
+        self.mutex.released.notify();
+    }
+}
@@ -0,0 +1,232 @@
+use crate::l3gd20::async_spi::AsyncSpiDevice;
+use crate::l3gd20::orientation::Orientation;
+use crate::l3gd20::{FullScale, L3gd20};
+use crate::lsm303dlhc::i2c_target::RegisterMap;
+use crate::lsm303dlhc::{AsyncI2cDevice, Lsm303dlhc};
+use crate::mutex::Mutex;
+use libm::roundf;
+
+/// Reads [ID]
+pub const WHO_AM_I: u8 = 0x00;
+/// Error flags of the last snapshot or [GYRO_RANGE] write, see [STATUS_GYRO_ERROR]
+pub const STATUS: u8 = 0x01;
+/// Write only commands, see [CONTROL_RESET]
+pub const CONTROL: u8 = 0x02;
+/// [FullScale] of the gyro: 0 = 250°/s, 1 = 500°/s, 2 = 2000°/s
+pub const GYRO_RANGE: u8 = 0x03;
+/// Orientation quaternion w, x, y, z in Q14 (16384 = 1.0)
+pub const QUATERNION: u8 = 0x04;
+/// Roll, pitch and yaw in 0.01°
+pub const ANGLES: u8 = 0x0C;
+/// Angular rates x, y, z of the last integrated sample in counts with the bias removed, see [GYRO_RANGE] for the
+/// sensitivity
+pub const RATE: u8 = 0x12;
+/// Acceleration x, y, z in mg
+pub const ACCEL: u8 = 0x18;
+/// Magnetic field x, y, z in raw counts
+pub const MAG: u8 = 0x1E;
+/// Registers from here on read 0
+pub const END: u8 = 0x24;
+
+pub const ID: u8 = 0x57;
+pub const STATUS_GYRO_ERROR: u8 = 1 << 0;
+pub const STATUS_ACCEL_ERROR: u8 = 1 << 1;
+pub const STATUS_MAG_ERROR: u8 = 1 << 2;
+/// Sets the current orientation as new zero
+pub const CONTROL_RESET: u8 = 1 << 0;
+/// Sets the current heading as zero yaw
+pub const CONTROL_ZERO_YAW: u8 = 1 << 1;
+
+/// [RegisterMap] of the orientation and the sensor data of the board
+///
+/// | Register        | Content                                        | Access |
+/// |-----------------|------------------------------------------------|--------|
+/// | 0x00 WHO_AM_I   | [ID]                                           | r      |
+/// | 0x01 STATUS     | error flags of the snapshot or range write     | r      |
+/// | 0x02 CONTROL    | [CONTROL_RESET], [CONTROL_ZERO_YAW], reads 0   | w      |
+/// | 0x03 GYRO_RANGE | full scale of the gyro                         | rw     |
+/// | 0x04..0x0B      | quaternion w, x, y, z in Q14                   | r      |
+/// | 0x0C..0x11      | roll, pitch, yaw in 0.01°                      | r      |
+/// | 0x12..0x17      | rates x, y, z of the last sample in counts     | r      |
+/// | 0x18..0x1D      | acceleration x, y, z in mg                     | r      |
+/// | 0x1E..0x23      | magnetic field x, y, z in counts               | r      |
+///
+/// Values wider than a byte are little endian i16. A host read takes a snapshot of the registers from its start
+/// register on, so all bytes of one read belong together. The compass is read while the host waits with SCL
+/// stretched. The orientation is integrated by another task sharing the same [Mutex]es, e.g. with
+/// [L3gd20::read_sample_at_edge] and [Orientation::update] (see example 10). The gyro itself is not read: that would
+/// take the data ready of the integrating task, the rates are those of its last sample ([Orientation::rate]).
+pub struct SensorRegisters<'a, G: AsyncSpiDevice, A: AsyncI2cDevice> {
+    gyro: &'a Mutex<L3gd20<G>>,
+    compass: &'a Mutex<Lsm303dlhc<A>>,
+    orientation: &'a Mutex<Orientation>,
+    image: [u8; END as usize],
+}
+
+impl<'a, G: AsyncSpiDevice, A: AsyncI2cDevice> SensorRegisters<'a, G, A> {
+    pub fn new(
+        gyro: &'a Mutex<L3gd20<G>>,
+        compass: &'a Mutex<Lsm303dlhc<A>>,
+        orientation: &'a Mutex<Orientation>,
+    ) -> Self {
+        let mut image = [0; END as usize];
+        image[WHO_AM_I as usize] = ID;
+        Self {
+            gyro,
+            compass,
+            orientation,
+            image,
+        }
+    }
+    /// Reads all values from `register` on into the image
+    async fn snapshot(&mut self, register: u8) {
+        if register < ACCEL {
+            let rate = {
+                let orientation = self.orientation.lock().await;
+                if register < RATE {
+                    encode_orientation(&orientation, &mut self.image);
+                }
+                orientation.rate()
+            };
+            let full_scale = self.gyro.lock().await.full_scale();
+            self.image[GYRO_RANGE as usize] = range_register(full_scale);
+            encode_axes(
+                &mut self.image[RATE as usize..],
+                rate_counts(rate, full_scale),
+            );
+        }
+        if register < END {
+            let (accel, mag) = {
+                let mut compass = self.compass.lock().await;
+                (
+                    compass.get_acceleration().await,
+                    compass.get_orientation().await,
+                )
+            };
+            self.update(ACCEL, STATUS_ACCEL_ERROR, accel.ok());
+            self.update(MAG, STATUS_MAG_ERROR, mag.ok());
+        }
+    }
+    /// Stores the axes of a sensor at `start` or sets its `error` flag and zeroes them if the read failed
+    fn update(&mut self, start: u8, error: u8, axes: Option<(i16, i16, i16)>) {
+        let status = &mut self.image[STATUS as usize];
+        *status &= !error;
+        let (x, y, z) = axes.unwrap_or_else(|| {
+            *status |= error;
+            (0, 0, 0)
+        });
+        encode_axes(&mut self.image[start as usize..], [x, y, z]);
+    }
+    /// Applies a host write of `value` to `register`, read only registers are ignored
+    async fn write_register(&mut self, register: u8, value: u8) {
+        match register {
+            CONTROL => {
+                let mut orientation = self.orientation.lock().await;
+                if value & CONTROL_RESET != 0 {
+                    orientation.reset();
+                }
+                if value & CONTROL_ZERO_YAW != 0 {
+                    orientation.zero_yaw();
+                }
+            }
+            GYRO_RANGE => {
+                let full_scale = match value {
+                    0 => FullScale::Dps250,
+                    1 => FullScale::Dps500,
+                    2 => FullScale::Dps2000,
+                    _ => return,
+                };
+                let mut gyro = self.gyro.lock().await;
+                let status = &mut self.image[STATUS as usize];
+                match gyro.set_full_scale(full_scale).await {
+                    Ok(()) => *status &= !STATUS_GYRO_ERROR,
+                    Err(_) => *status |= STATUS_GYRO_ERROR,
+                }
+                self.image[GYRO_RANGE as usize] = range_register(gyro.full_scale());
+            }
+            _ => {}
+        }
+    }
+}
+
+impl<'a, G: AsyncSpiDevice, A: AsyncI2cDevice> RegisterMap for SensorRegisters<'a, G, A> {
+    async fn write(&mut self, register: u8, data: &[u8]) {
+        for (i, value) in data.iter().enumerate() {
+            self.write_register(register.wrapping_add(i as u8), *value)
+                .await;
+        }
+    }
+    async fn read(&mut self, register: u8, buf: &mut [u8]) {
+        for (i, byte) in buf.iter_mut().enumerate() {
+            let register = register.wrapping_add(i as u8) as usize;
+            *byte = self.image.get(register).copied().unwrap_or(0);
+        }
+    }
+    async fn begin_read(&mut self, register: u8) {
+        self.snapshot(register).await;
+    }
+}
+
+fn range_register(full_scale: FullScale) -> u8 {
+    match full_scale {
+        FullScale::Dps250 => 0,
+        FullScale::Dps500 => 1,
+        FullScale::Dps2000 => 2,
+    }
+}
+
+/// Converts rates in °/s back to counts of the gyro at `full_scale`
+fn rate_counts(rate: [f32; 3], full_scale: FullScale) -> [i16; 3] {
+    rate.map(|r| roundf(r / full_scale.sensitivity()) as i16)
+}
+
+fn encode_axes(registers: &mut [u8], values: [i16; 3]) {
+    for (chunk, value) in registers.chunks_mut(2).zip(values) {
+        chunk.copy_from_slice(&value.to_le_bytes());
+    }
+}
+
+fn encode_orientation(orientation: &Orientation, image: &mut [u8]) {
+    let q = orientation.quaternion();
+    for (i, value) in [q.w, q.x, q.y, q.z].into_iter().enumerate() {
+        let start = QUATERNION as usize + 2 * i;
+        image[start..start + 2].copy_from_slice(&((value * 16384.0) as i16).to_le_bytes());
+    }
+    let angles = orientation.angles();
+    let angles = [angles.roll, angles.pitch, angles.yaw].map(|a| (a * 100.0) as i16);
+    encode_axes(&mut image[ANGLES as usize..], angles);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::l3gd20::orientation::RateSample;
+
+    #[test]
+    fn orientation_encoding() {
+        let mut orientation = Orientation::new();
+        let mut image = [0; END as usize];
+        encode_orientation(&orientation, &mut image);
+        assert_eq!(
+            &image[QUATERNION as usize..ANGLES as usize],
+            &[0x00, 0x40, 0, 0, 0, 0, 0, 0]
+        );
+        // 90° yaw in one second
+        for t in [0, 1_000_000] {
+            orientation.update(&RateSample {
+                timestamp_us: t,
+                rate: [0.0, 0.0, 90.0],
+            });
+        }
+        encode_orientation(&orientation, &mut image);
+        let yaw = i16::from_le_bytes([image[ANGLES as usize + 4], image[ANGLES as usize + 5]]);
+        assert!((8990..=9010).contains(&yaw), "yaw {}", yaw);
+    }
+
+    #[test]
+    fn rates_are_served_in_counts() {
+        let rate = [0.0875, -1.75, 70.0];
+        assert_eq!(rate_counts(rate, FullScale::Dps250), [10, -200, 8000]);
+        assert_eq!(rate_counts(rate, FullScale::Dps2000), [1, -25, 1000]);
+    }
+}
@@ -1,15 +1,25 @@
 use super::button::simple_button::{Polarity, SimpleButton};
+use super::l3gd20::dma_spi::DmaSpi;
+use super::l3gd20::int_line::{IntLine, Line};
 use super::led::simple_led::SimpleLed;
-use super::lsm303dlhc::{i2c_no_irq::I2cNoIrq, Lsm303dlhc, MAGNETO_ADDR};
+use super::lsm303dlhc::{
+    dma_i2c::DmaI2c,
+    i2c_no_irq::I2cNoIrq,
+    i2c_target::{I2cTarget, TargetAddress},
+    shared_i2c::SharedI2cBus,
+};
 
+use cortex_m::peripheral::NVIC;
+use stm32f3xx_hal::dma::dma1;
 use stm32f3xx_hal::gpio::{
-    Alternate, Gpioa, Gpiob, Gpioe, Input, OpenDrain, Output, Pin, PushPull, U,
+    Alternate, Edge, Gpioa, Gpiob, Gpioe, Input, OpenDrain, Output, Pin, PushPull, U,
 };
 use stm32f3xx_hal::i2c::I2c;
-use stm32f3xx_hal::pac::I2C1;
+use stm32f3xx_hal::pac::{Interrupt, I2C1, I2C2};
 use stm32f3xx_hal::prelude::*;
 use stm32f3xx_hal::rcc::Clocks;
 use stm32f3xx_hal::spi::{config::Config, Spi};
+use stm32f3xx_hal::syscfg::SysCfgExt;
 use stm32f3xx_hal::time::rate::Kilohertz;
 
 pub type NorthEastLed = SimpleLed<Pin<Gpioe, U<8>, Output<PushPull>>>;
@@ -21,21 +31,40 @@ pub type SouthLed = SimpleLed<Pin<Gpioe, U<13>, Output<PushPull>>>;
 pub type SouthEastLed = SimpleLed<Pin<Gpioe, U<14>, Output<PushPull>>>;
 pub type EastLed = SimpleLed<Pin<Gpioe, U<15>, Output<PushPull>>>;
 pub type GyroCs = Pin<Gpioe, U<3>, Output<PushPull>>;
+pub type GyroInt1Pin = Pin<Gpioe, U<0>, Input>;
+pub type GyroInt1 = IntLine<GyroInt1Pin>;
+pub type GyroInt2Pin = Pin<Gpioe, U<1>, Input>;
+pub type GyroInt2 = IntLine<GyroInt2Pin>;
 pub type UserButPin = Pin<Gpioa, U<0>, Input>;
 pub type UserButton = SimpleButton<UserButPin>;
-pub type GyroSpi = Spi<
-    stm32f3xx_hal::pac::SPI1,
-    (
-        Pin<Gpioa, U<5>, Alternate<PushPull, 5>>,
-        Pin<Gpioa, U<6>, Alternate<PushPull, 5>>,
-        Pin<Gpioa, U<7>, Alternate<PushPull, 5>>,
-    ),
-    u8,
->;
+pub type GyroSpiPins = (
+    Pin<Gpioa, U<5>, Alternate<PushPull, 5>>,
+    Pin<Gpioa, U<6>, Alternate<PushPull, 5>>,
+    Pin<Gpioa, U<7>, Alternate<PushPull, 5>>,
+);
+pub type GyroSpi = Spi<stm32f3xx_hal::pac::SPI1, GyroSpiPins, u8>;
+/// Build with `DmaSpi::new(b.gyro_spi, b.dma1.ch2, b.dma1.ch3)`
+pub type GyroDmaSpi = DmaSpi<GyroSpiPins>;
 
 pub type GyroScl = Pin<Gpiob, U<6>, Alternate<OpenDrain, 4>>;
 pub type GyroSda = Pin<Gpiob, U<7>, Alternate<OpenDrain, 4>>;
 pub type GyroI2c = I2c<stm32f3xx_hal::pac::I2C1, (GyroScl, GyroSda)>;
+/// Shared I2C1 of the LSM303DLHC, hand out the devices with `Lsm303dlhc::new(b.i2c.device(ACCEL_ADDR), b.i2c.device(MAGNETO_ADDR))`
+///
+/// Release it with `b.i2c.release().release()` to switch to [super::lsm303dlhc::i2c_irq::I2cIrq].
+pub type BoardI2c = SharedI2cBus<I2cNoIrq<I2C1, GyroScl, GyroSda>>;
+/// Build with `DmaI2c::new(b.i2c.release().release(), b.dma1.ch6, b.dma1.ch7).with_bus_pins()`
+pub type BoardDmaI2c = DmaI2c<GyroScl, GyroSda>;
+
+pub type HostScl = Pin<Gpioa, U<9>, Alternate<OpenDrain, 4>>;
+pub type HostSda = Pin<Gpioa, U<10>, Alternate<OpenDrain, 4>>;
+/// I2C2 answering a host at [HOST_ADDRESS], serve e.g. a [super::sensor_registers::SensorRegisters] with it
+pub type HostI2c = I2cTarget<I2C2, HostScl, HostSda>;
+/// Own address of [HostI2c]
+pub const HOST_ADDRESS: TargetAddress = match TargetAddress::new(0x2A) {
+    Some(address) => address,
+    None => panic!("reserved I2C address"),
+};
 
 pub struct Board {
     pub northeast_led: NorthEastLed,
@@ -49,8 +78,13 @@ pub struct Board {
     pub user_button: UserButton,
     pub gyro_spi: GyroSpi,
     pub gyro_cs: GyroCs,
+    pub gyro_int1: GyroInt1,
+    pub gyro_int2: GyroInt2,
     pub clocks: Clocks,
-    pub magnetometer: Lsm303dlhc<I2C1, GyroScl, GyroSda>,
+    /// Channel 2 and 3 are used by [DmaSpi]
+    pub dma1: dma1::Channels,
+    pub i2c: BoardI2c,
+    pub host_i2c: HostI2c,
 }
 
 impl Board {
@@ -59,6 +93,7 @@ impl Board {
         let mut gpioa = p.GPIOA.split(&mut rcc.ahb);
         let mut gpiob = p.GPIOB.split(&mut rcc.ahb);
         let mut gpioe = p.GPIOE.split(&mut rcc.ahb);
+        let dma1 = p.DMA1.split(&mut rcc.ahb);
 
         let pe8 = gpioe
             .pe8
@@ -90,6 +125,24 @@ impl Board {
 
         let pa0 = gpioa.pa0.into_input(&mut gpioa.moder);
 
+        // ----------- GYRO INT -------------------
+        let mut syscfg = p.SYSCFG.constrain(&mut rcc.apb2);
+        let mut exti = p.EXTI;
+        let mut pe0 = gpioe.pe0.into_input(&mut gpioe.moder);
+        pe0.make_interrupt_source(&mut syscfg);
+        pe0.trigger_on_edge(&mut exti, Edge::Rising);
+        let mut pe1 = gpioe.pe1.into_input(&mut gpioe.moder);
+        pe1.make_interrupt_source(&mut syscfg);
+        pe1.trigger_on_edge(&mut exti, Edge::Rising);
+        // EXTI Lines themselves are armed by the waiting task
+        unsafe {
+            NVIC::unmask(pe0.interrupt());
+            NVIC::unmask(pe1.interrupt());
+        }
+        let gyro_int1: GyroInt1 = IntLine::new(pe0, Line::Int1);
+        let gyro_int2: GyroInt2 = IntLine::new(pe1, Line::Int2);
+        // ----------- GYRO INT -------------------
+
         let northeast_led: NorthEastLed = SimpleLed::new(pe8);
         let north_led: NorthLed = SimpleLed::new(pe9);
         let northwest_led: NorthWestLed = SimpleLed::new(pe10);
@@ -119,6 +172,8 @@ impl Board {
         let config = Config::default().frequency(Kilohertz::new(1));
 
         let spi: Spi<_, _, u8> = Spi::new(p.SPI1, (sck, miso, mosi), config, r, &mut rcc.apb2);
+        // RXNE/TXE interrupts are enabled by the waiting futures
+        unsafe { NVIC::unmask(Interrupt::SPI1) };
         // ----------- GYRO SPI -------------------
 
         let scl =
@@ -132,8 +187,24 @@ impl Board {
 
         let i2c = I2c::new(p.I2C1, (scl, sda), 100000.Hz(), r, &mut rcc.apb1);
 
-        let async_i2c = I2cNoIrq::new(i2c, MAGNETO_ADDR);
-        let magnetometer = Lsm303dlhc::new(async_i2c);
+        let mut i2c = I2cNoIrq::new(i2c).with_bus_pins();
+        // I2C1 runs on HSI (8MHz) after reset, 25ms is the SMBus clock low timeout
+        i2c.enable_clock_timeout(25.milliseconds(), 8.MHz().into());
+        let i2c = SharedI2cBus::new(i2c);
+
+        // ----------- HOST I2C -------------------
+        let host_scl =
+            gpioa
+                .pa9
+                .into_af_open_drain::<4>(&mut gpioa.moder, &mut gpioa.otyper, &mut gpioa.afrh);
+        let host_sda = gpioa.pa10.into_af_open_drain::<4>(
+            &mut gpioa.moder,
+            &mut gpioa.otyper,
+            &mut gpioa.afrh,
+        );
+        let host_i2c = I2c::new(p.I2C2, (host_scl, host_sda), 100000.Hz(), r, &mut rcc.apb1);
+        let host_i2c = I2cTarget::new(host_i2c, HOST_ADDRESS);
+        // ----------- HOST I2C -------------------
 
         let ba = Board {
             northeast_led,
@@ -147,8 +218,12 @@ impl Board {
             user_button,
             gyro_spi: spi,
             gyro_cs: pe3,
+            gyro_int1,
+            gyro_int2,
             clocks: r,
-            magnetometer,
+            dma1,
+            i2c,
+            host_i2c,
         };
 
         ba